@@ -1,9 +1,23 @@
 //! A module dedicated to generic [`Cache`] trait and its implementations provided by this crate.
 mod concurrent;
+mod lru;
+mod noop;
+mod statistics;
+
+pub use self::lru::LruCache;
+pub use concurrent::ConcurrentCache;
+pub use noop::*;
+pub use statistics::CacheStatistics;
+pub(crate) use statistics::StatisticsCounter;
+
+use crate::error::Result;
+
+/// A predicate of [`Cache::invalidate_if`] that selects key-value pairs to discard.
+pub type Predicate<K, V> = Box<dyn Fn(&K, &V) -> bool + Send + Sync>;
 
 /// A trait for objects that implement fast key-value storage.
 ///
-/// Implementor may choose to support certain eviction policy. The cache is defined by three methods
+/// Implementor may choose to support certain eviction policy. The cache is defined by these methods
 ///
 /// * The `read` method tries to load a value that is associated with given `key`. Successfully
 ///   loading the value from cache is referred to as a "hit" and correspondingly as "miss" to the
@@ -13,10 +27,36 @@ mod concurrent;
 ///   If not, it is entered as a new key-value pair. Subsequently, if maximum capacity is reached
 ///   a certain different key-value pair is evicted from the cache. Which particular pair gets
 ///   evicted is based on a policy of the implementor.
+/// * The `write_evicting` method works like `write` and returns key-value pairs the write evicted
+///   to make room for the written one. It has a default implementation composed of `write` that
+///   reports no evictions, which suits caches that evict asynchronously. The implementor that
+///   evicts synchronously should override it, so that the caller can act upon evicted values.
 /// * The `contains` method checks if there is a cache hit for given key and has a default
 ///   implementation that uses the `read` method. The implementor may choose to implement this
 ///   method differently if there is a more efficient way to do it or if calling the `read` method
 ///   messes with the eviction policy.
+/// * The `read_or_write_with` method reads a value that is associated with given `key` and on a
+///   miss writes a value produced by `init`, unless it produces [`None`]. It has a default
+///   implementation composed of `read` and `write`. The implementor should override it if it can
+///   guarantee that concurrent misses of the same key evaluate `init` only once and that the
+///   written value is returned even if it gets evicted in the meantime.
+/// * The `try_read_or_write_with` method works like `read_or_write_with` with an `init` that may
+///   fail. The failure is returned and nothing is written. The implementor that evaluates `init`
+///   only once for concurrent misses should return the failure to every such caller, rather than
+///   report the value as missing to those that did not evaluate it.
+/// * The `read_many` and `write_many` methods read and write a batch of key-value pairs. They
+///   have default implementations composed of `read` and `write`. The implementor should override
+///   them if it can access a batch more efficiently than key by key.
+/// * The `invalidate` method discards a value associated with given `key`, if any. Subsequent
+///   `read` of such `key` is a miss.
+/// * The `invalidate_if` method discards every key-value pair for which the `predicate` returns
+///   `true`. The implementor may apply the `predicate` lazily, as long as no such pair is read
+///   afterwards.
+/// * The `invalidate_all` method discards every key-value pair, leaving the cache empty.
+/// * The `statistics` method takes a snapshot of [`CacheStatistics`]. It has a default
+///   implementation that reports no statistics at all.
+///
+/// The trait is object safe, except for methods that take a generic `init` closure.
 pub trait Cache<K, V> {
     fn contains(&self, key: &K) -> bool {
         self.read(key).is_some()
@@ -24,4 +64,64 @@ pub trait Cache<K, V> {
 
     fn read(&self, key: &K) -> Option<V>;
     fn write(&self, key: K, value: V);
+
+    fn write_evicting(&self, key: K, value: V) -> Vec<(K, V)> {
+        self.write(key, value);
+
+        Vec::new()
+    }
+
+    fn read_or_write_with(&self, key: K, init: impl FnOnce() -> Option<V>) -> Option<V>
+    where
+        Self: Sized,
+        V: Clone,
+    {
+        if let Some(value) = self.read(&key) {
+            return Some(value);
+        }
+
+        let value = init()?;
+        self.write(key, value.clone());
+
+        Some(value)
+    }
+
+    fn try_read_or_write_with(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<Option<V>>,
+    ) -> Result<Option<V>>
+    where
+        Self: Sized,
+        V: Clone,
+    {
+        if let Some(value) = self.read(&key) {
+            return Ok(Some(value));
+        }
+
+        let value = init()?;
+        if let Some(value) = &value {
+            self.write(key, value.clone());
+        }
+
+        Ok(value)
+    }
+
+    fn read_many(&self, keys: &[K]) -> Vec<Option<V>> {
+        keys.iter().map(|key| self.read(key)).collect()
+    }
+
+    fn write_many(&self, entries: Vec<(K, V)>) {
+        for (key, value) in entries {
+            self.write(key, value);
+        }
+    }
+
+    fn invalidate(&self, key: &K);
+    fn invalidate_if(&self, predicate: Predicate<K, V>);
+    fn invalidate_all(&self);
+
+    fn statistics(&self) -> CacheStatistics {
+        CacheStatistics::default()
+    }
 }
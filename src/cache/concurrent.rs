@@ -11,10 +11,153 @@
 //!
 //! let actual = cache.read(&1).expect("Key 1 was just written");
 //! assert_eq!(actual, "phylax");
+//!
+//! cache.invalidate(&1);
+//! assert!(!cache.contains(&1));
 //! ```
-use crate::cache::Cache;
+//!
+//! A bare [`Moka`] cache does not report any [`CacheStatistics`] but the number of entries. The
+//! [`ConcurrentCache`] created by [`CacheBuilder`](crate::CacheBuilder) wraps it and records them.
+use crate::cache::{Cache, CacheStatistics, Predicate, StatisticsCounter};
+use crate::error::{Error, Result};
 use moka::sync::Cache as Moka;
+use moka::PredicateError;
 use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Thread-safe [`Cache`] with concurrent access that records [`CacheStatistics`].
+///
+/// Built by [`CacheBuilder`](crate::CacheBuilder) on top of a [`Moka`] cache.
+#[derive(Clone)]
+pub struct ConcurrentCache<K, V> {
+    cache: Moka<K, V>,
+    statistics: Arc<StatisticsCounter>,
+}
+
+impl<K, V> ConcurrentCache<K, V>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Builds the cache by the `builder` while attaching a listener that counts evictions and
+    /// enabling invalidation by predicates.
+    pub(crate) fn new(builder: moka::sync::CacheBuilder<K, V, Moka<K, V>>) -> Self {
+        let statistics = Arc::new(StatisticsCounter::default());
+        let listener_statistics = Arc::clone(&statistics);
+        let cache = builder
+            .support_invalidation_closures()
+            .eviction_listener(move |_key, _value, cause| {
+                if cause.was_evicted() {
+                    listener_statistics.record_eviction();
+                }
+            })
+            .build();
+
+        Self { cache, statistics }
+    }
+}
+
+impl<K, V> Cache<K, V> for ConcurrentCache<K, V>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn contains(&self, key: &K) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    fn read(&self, key: &K) -> Option<V> {
+        let value = self.cache.read(key);
+
+        match value {
+            Some(_) => self.statistics.record_hit(),
+            None => self.statistics.record_miss(),
+        }
+
+        value
+    }
+
+    fn write(&self, key: K, value: V) {
+        self.statistics.record_insert();
+        self.cache.write(key, value);
+    }
+
+    fn read_many(&self, keys: &[K]) -> Vec<Option<V>> {
+        let values: Vec<_> = keys.iter().map(|key| self.cache.get(key)).collect();
+        let hits = values.iter().filter(|value| value.is_some()).count() as u64;
+
+        self.statistics.record_reads(hits, keys.len() as u64 - hits);
+
+        values
+    }
+
+    fn write_many(&self, entries: Vec<(K, V)>) {
+        self.statistics.record_inserts(entries.len() as u64);
+
+        for (key, value) in entries {
+            self.cache.insert(key, value);
+        }
+    }
+
+    fn read_or_write_with(&self, key: K, init: impl FnOnce() -> Option<V>) -> Option<V> {
+        self.try_read_or_write_with(key, || Ok(init()))
+            .ok()
+            .flatten()
+    }
+
+    fn try_read_or_write_with(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<Option<V>>,
+    ) -> Result<Option<V>> {
+        let mut loaded = false;
+        let value = self.cache.try_read_or_write_with(key, || {
+            loaded = true;
+            let start = Instant::now();
+            let value = init();
+            self.statistics.record_load(start.elapsed());
+
+            if let Ok(Some(_)) = value {
+                self.statistics.record_insert();
+            }
+
+            value
+        });
+
+        match (loaded, &value) {
+            (false, Ok(Some(_))) => self.statistics.record_hit(),
+            _ => self.statistics.record_miss(),
+        }
+
+        value
+    }
+
+    fn invalidate(&self, key: &K) {
+        self.cache.invalidate(key);
+    }
+
+    fn invalidate_if(&self, predicate: Predicate<K, V>) {
+        Cache::invalidate_if(&self.cache, predicate);
+    }
+
+    fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+    }
+
+    /// Takes a snapshot of [`CacheStatistics`] after performing pending maintenance, so that the
+    /// number of entries and evictions is up-to-date.
+    fn statistics(&self) -> CacheStatistics {
+        self.cache.run_pending_tasks();
+        self.statistics.snapshot(self.cache.entry_count())
+    }
+}
+
+/// A reason why a load of a missing value has not written anything into a [`Moka`] cache.
+enum Unloaded {
+    Absent,
+    Failed(Arc<Error>),
+}
 
 impl<K, V> Cache<K, V> for Moka<K, V>
 where
@@ -28,4 +171,64 @@ where
     fn write(&self, key: K, value: V) {
         Moka::insert(self, key, value);
     }
+
+    fn read_or_write_with(&self, key: K, init: impl FnOnce() -> Option<V>) -> Option<V> {
+        Moka::optionally_get_with(self, key, init)
+    }
+
+    /// Evaluates `init` once for concurrent misses of the same `key` and returns its failure to
+    /// every such caller.
+    fn try_read_or_write_with(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<Option<V>>,
+    ) -> Result<Option<V>> {
+        let value = Moka::try_get_with(self, key, || match init() {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(Unloaded::Absent),
+            Err(error) => Err(Unloaded::Failed(Arc::new(error))),
+        });
+
+        match value {
+            Ok(value) => Ok(Some(value)),
+            Err(unloaded) => match &*unloaded {
+                Unloaded::Absent => Ok(None),
+                Unloaded::Failed(error) => Err(Error::shared(Arc::clone(error))),
+            },
+        }
+    }
+
+    fn invalidate(&self, key: &K) {
+        Moka::invalidate(self, key);
+    }
+
+    /// Registers the `predicate` to be applied lazily, which takes constant time. Unless the
+    /// cache was built with invalidation closures supported, it falls back to iterating over
+    /// every entry instead.
+    fn invalidate_if(&self, predicate: Predicate<K, V>) {
+        let predicate = Arc::new(predicate);
+        let lazy_predicate = Arc::clone(&predicate);
+        let Err(PredicateError::InvalidationClosuresDisabled) =
+            Moka::invalidate_entries_if(self, move |key, value| lazy_predicate(key, value))
+        else {
+            return;
+        };
+
+        for (key, value) in Moka::iter(self) {
+            if predicate(&key, &value) {
+                Moka::invalidate(self, key.as_ref());
+            }
+        }
+    }
+
+    fn invalidate_all(&self) {
+        Moka::invalidate_all(self);
+    }
+
+    fn statistics(&self) -> CacheStatistics {
+        CacheStatistics {
+            entry_count: Moka::entry_count(self),
+            ..CacheStatistics::default()
+        }
+    }
 }
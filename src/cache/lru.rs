@@ -0,0 +1,384 @@
+//! Thread-safe [`Cache`] implementation with an exact least recently used eviction policy.
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{Cache, CacheBuilder};
+//! let cache = CacheBuilder::new().with_capacity(2).build_lru();
+//!
+//! cache.write(1, "phylax");
+//! cache.write(2, "centurion");
+//! cache.read(&1);
+//! cache.write(3, "optio");
+//!
+//! assert!(cache.contains(&1));
+//! assert!(!cache.contains(&2));
+//! ```
+use crate::cache::{Cache, CacheStatistics, Predicate, StatisticsCounter};
+use crate::error::{Error, Result};
+use ::lru::LruCache as Lru;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+/// Thread-safe [`Cache`] that evicts exactly the least recently used entry.
+///
+/// Unlike [`ConcurrentCache`](crate::ConcurrentCache), capacity is enforced synchronously by
+/// every write, which makes the eviction deterministic. Entries are guarded by a mutex that can be
+/// split into shards to lower contention. Each shard then holds its own share of the capacity and
+/// evicts the least recently used entry among its own entries only.
+///
+/// Missing values are loaded without holding any shard locked, so a slow load blocks neither
+/// other keys nor the same cache accessed from within the load. Concurrent misses of the same key
+/// wait for a single load instead.
+///
+/// Built by [`CacheBuilder`](crate::CacheBuilder).
+pub struct LruCache<K, V> {
+    shards: Box<[Mutex<Lru<K, V>>]>,
+    loads: Mutex<HashMap<K, Arc<Load<V>>>>,
+    statistics: StatisticsCounter,
+}
+
+/// A load of a missing value that concurrent misses of the same key wait for.
+struct Load<V> {
+    outcome: Mutex<Option<Outcome<V>>>,
+    finished: Condvar,
+}
+
+enum Outcome<V> {
+    Loaded(Result<Option<V>, Arc<Error>>),
+    /// The load did not finish, e.g. due to a panic, and the waiting misses have to retry.
+    Abandoned,
+}
+
+impl<V: Clone> Load<V> {
+    fn new() -> Self {
+        Self {
+            outcome: Mutex::new(None),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Publishes the `outcome` to every waiting miss, unless some outcome is published already.
+    fn finish(&self, outcome: Outcome<V>) {
+        let mut current = self.outcome.lock().unwrap_or_else(PoisonError::into_inner);
+        if current.is_none() {
+            current.replace(outcome);
+            self.finished.notify_all();
+        }
+    }
+
+    /// Waits for the load to finish and returns its result, or [`None`] if it was abandoned.
+    fn wait(&self) -> Option<Result<Option<V>>> {
+        let outcome = self
+            .finished
+            .wait_while(
+                self.outcome.lock().unwrap_or_else(PoisonError::into_inner),
+                |outcome| outcome.is_none(),
+            )
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match outcome.as_ref()? {
+            Outcome::Loaded(result) => Some(result.clone().map_err(Error::shared)),
+            Outcome::Abandoned => None,
+        }
+    }
+}
+
+/// Membership of a miss in a [`Load`] of its key.
+enum Flight<V> {
+    /// The value was written meanwhile and no load is needed.
+    Landed(V),
+    /// The miss performs the load.
+    Leading(Arc<Load<V>>),
+    /// The miss waits for a load performed by another one.
+    Following(Arc<Load<V>>),
+}
+
+/// Removes a [`Load`] from the cache once the leading miss is done with it and abandons the load
+/// if it has not finished, so that waiting misses never wait forever.
+struct LoadGuard<'a, K: Hash + Eq, V: Clone> {
+    loads: &'a Mutex<HashMap<K, Arc<Load<V>>>>,
+    key: &'a K,
+    load: Arc<Load<V>>,
+}
+
+impl<K: Hash + Eq, V: Clone> Drop for LoadGuard<'_, K, V> {
+    fn drop(&mut self) {
+        let mut loads = self.loads.lock().unwrap_or_else(PoisonError::into_inner);
+        if matches!(loads.get(self.key), Some(load) if Arc::ptr_eq(load, &self.load)) {
+            loads.remove(self.key);
+        }
+        drop(loads);
+
+        self.load.finish(Outcome::Abandoned);
+    }
+}
+
+impl<K: Hash + Eq, V> LruCache<K, V> {
+    /// Creates the cache holding at most `capacity` entries split evenly into `shards`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is lower than the number of `shards` or there are no `shards`.
+    pub(crate) fn new(capacity: usize, shards: usize) -> Self {
+        assert!(shards > 0, "There must be at least one shard");
+        assert!(
+            capacity >= shards,
+            "Capacity must be at least the number of shards"
+        );
+
+        Self {
+            shards: (0..shards)
+                .map(|shard| {
+                    let remainder = usize::from(shard < capacity % shards);
+                    let capacity = NonZeroUsize::new(capacity / shards + remainder)
+                        .expect("Capacity is at least the number of shards");
+                    Mutex::new(Lru::new(capacity))
+                })
+                .collect(),
+            loads: Mutex::default(),
+            statistics: StatisticsCounter::default(),
+        }
+    }
+
+    fn shard_index(&self, key: &K) -> usize {
+        // Hasher with fixed keys keeps the assignment of keys to shards the same across runs
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    fn lock_shard(&self, index: usize) -> MutexGuard<'_, Lru<K, V>> {
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn shard(&self, key: &K) -> MutexGuard<'_, Lru<K, V>> {
+        self.lock_shard(self.shard_index(key))
+    }
+
+    /// Groups positions of `keys` by the index of their shard, so that a batch locks every shard
+    /// at most once. Positions within a group keep their order.
+    fn positions_by_shard<'k>(&self, keys: impl Iterator<Item = &'k K>) -> Vec<(usize, Vec<usize>)>
+    where
+        K: 'k,
+    {
+        let mut groups = vec![Vec::new(); self.shards.len()];
+        for (position, key) in keys.enumerate() {
+            groups[self.shard_index(key)].push(position);
+        }
+
+        groups
+            .into_iter()
+            .enumerate()
+            .filter(|(_index, positions)| !positions.is_empty())
+            .collect()
+    }
+
+    /// Joins a [`Load`] of the `key` in progress, or starts one if there is none. The shard is
+    /// checked again while loads are locked, since a load may have just written the value.
+    fn join_load(&self, key: &K) -> Flight<V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut loads = self.loads.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(load) = loads.get(key) {
+            return Flight::Following(Arc::clone(load));
+        }
+        if let Some(value) = self.shard(key).get(key) {
+            return Flight::Landed(value.clone());
+        }
+
+        let load = Arc::new(Load::new());
+        loads.insert(key.clone(), Arc::clone(&load));
+
+        Flight::Leading(load)
+    }
+
+    /// Writes the entry into the `shard` and returns the entry evicted to make room for it, if any.
+    fn push(&self, shard: &mut Lru<K, V>, key: K, value: V) -> Option<(K, V)> {
+        let replaced = shard.contains(&key);
+
+        self.statistics.record_insert();
+
+        let evicted = shard.push(key, value).filter(|_entry| !replaced);
+        if evicted.is_some() {
+            self.statistics.record_eviction();
+        }
+
+        evicted
+    }
+}
+
+impl<K, V> Cache<K, V> for LruCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn contains(&self, key: &K) -> bool {
+        self.shard(key).contains(key)
+    }
+
+    fn read(&self, key: &K) -> Option<V> {
+        let value = self.shard(key).get(key).cloned();
+
+        match value {
+            Some(_) => self.statistics.record_hit(),
+            None => self.statistics.record_miss(),
+        }
+
+        value
+    }
+
+    fn write(&self, key: K, value: V) {
+        let mut shard = self.shard(&key);
+
+        self.push(&mut shard, key, value);
+    }
+
+    /// Writes the entry and returns the least recently used entry of its shard, if it was evicted.
+    fn write_evicting(&self, key: K, value: V) -> Vec<(K, V)> {
+        let mut shard = self.shard(&key);
+
+        self.push(&mut shard, key, value).into_iter().collect()
+    }
+
+    fn read_many(&self, keys: &[K]) -> Vec<Option<V>> {
+        let mut values = vec![None; keys.len()];
+        for (index, positions) in self.positions_by_shard(keys.iter()) {
+            let mut shard = self.lock_shard(index);
+            for position in positions {
+                values[position] = shard.get(&keys[position]).cloned();
+            }
+        }
+
+        let hits = values.iter().filter(|value| value.is_some()).count() as u64;
+        self.statistics.record_reads(hits, keys.len() as u64 - hits);
+
+        values
+    }
+
+    fn write_many(&self, entries: Vec<(K, V)>) {
+        let groups = self.positions_by_shard(entries.iter().map(|(key, _value)| key));
+        let mut entries: Vec<_> = entries.into_iter().map(Some).collect();
+
+        for (index, positions) in groups {
+            let mut shard = self.lock_shard(index);
+            for position in positions {
+                if let Some((key, value)) = entries[position].take() {
+                    self.push(&mut shard, key, value);
+                }
+            }
+        }
+    }
+
+    fn read_or_write_with(&self, key: K, init: impl FnOnce() -> Option<V>) -> Option<V> {
+        self.try_read_or_write_with(key, || Ok(init()))
+            .ok()
+            .flatten()
+    }
+
+    /// Reads a value associated with given `key` or writes the one produced by `init`, which runs
+    /// without the shard of the `key` locked. Concurrent misses of the same `key` wait for a single
+    /// evaluation of `init` and share its result, including a failure.
+    fn try_read_or_write_with(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<Option<V>>,
+    ) -> Result<Option<V>> {
+        let load = loop {
+            if let Some(value) = self.shard(&key).get(&key).cloned() {
+                self.statistics.record_hit();
+                return Ok(Some(value));
+            }
+
+            match self.join_load(&key) {
+                Flight::Landed(value) => {
+                    self.statistics.record_hit();
+                    return Ok(Some(value));
+                }
+                Flight::Leading(load) => break load,
+                Flight::Following(load) => match load.wait() {
+                    Some(value) => {
+                        match value {
+                            Ok(Some(_)) => self.statistics.record_hit(),
+                            _ => self.statistics.record_miss(),
+                        }
+                        return value;
+                    }
+                    None => continue,
+                },
+            }
+        };
+        let guard = LoadGuard {
+            loads: &self.loads,
+            key: &key,
+            load,
+        };
+
+        self.statistics.record_miss();
+
+        let start = Instant::now();
+        let value = init();
+        self.statistics.record_load(start.elapsed());
+
+        match value {
+            Ok(value) => {
+                if let Some(value) = &value {
+                    self.push(&mut self.shard(&key), key.clone(), value.clone());
+                }
+                guard.load.finish(Outcome::Loaded(Ok(value.clone())));
+
+                Ok(value)
+            }
+            Err(error) => {
+                let error = Arc::new(error);
+                guard.load.finish(Outcome::Loaded(Err(Arc::clone(&error))));
+                // Releases the load, so that the error is unwrapped unless a waiting miss shares it
+                drop(guard);
+
+                Err(Error::shared(error))
+            }
+        }
+    }
+
+    fn invalidate(&self, key: &K) {
+        self.shard(key).pop(key);
+    }
+
+    fn invalidate_if(&self, predicate: Predicate<K, V>) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+            let keys: Vec<K> = shard
+                .iter()
+                .filter(|(key, value)| predicate(key, value))
+                .map(|(key, _value)| key.clone())
+                .collect();
+
+            for key in keys {
+                shard.pop(&key);
+            }
+        }
+    }
+
+    fn invalidate_all(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+
+    fn statistics(&self) -> CacheStatistics {
+        let entry_count = self
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(PoisonError::into_inner).len() as u64)
+            .sum();
+
+        self.statistics.snapshot(entry_count)
+    }
+}
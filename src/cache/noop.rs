@@ -0,0 +1,26 @@
+//! A [`Cache`] implementation that never holds any entry.
+use crate::cache::{Cache, Predicate};
+
+/// A [`Cache`] that discards every written entry and therefore always misses.
+///
+/// Useful in place of a cache for data that should always be accessed directly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopCache;
+
+impl<K, V> Cache<K, V> for NoopCache {
+    fn contains(&self, _key: &K) -> bool {
+        false
+    }
+
+    fn read(&self, _key: &K) -> Option<V> {
+        None
+    }
+
+    fn write(&self, _key: K, _value: V) {}
+
+    fn invalidate(&self, _key: &K) {}
+
+    fn invalidate_if(&self, _predicate: Predicate<K, V>) {}
+
+    fn invalidate_all(&self) {}
+}
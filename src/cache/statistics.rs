@@ -0,0 +1,113 @@
+//! Statistics describing how efficiently a [`Cache`] serves its reads.
+//!
+//! [`Cache`]: crate::Cache
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A snapshot of statistics of a [`Cache`] taken at a certain point in time.
+///
+/// Counters are accumulated since the cache was created. Backends that do not track some of them
+/// report zero instead.
+///
+/// [`Cache`]: crate::Cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStatistics {
+    /// Number of reads that found a value in the cache.
+    pub hits: u64,
+    /// Number of reads that did not find a value in the cache.
+    pub misses: u64,
+    /// Number of values written into the cache.
+    pub inserts: u64,
+    /// Number of values evicted from the cache to keep it within its capacity.
+    pub evictions: u64,
+    /// Number of values currently held by the cache.
+    pub entry_count: u64,
+    /// Number of values loaded on a miss.
+    pub loads: u64,
+    /// Total time spent loading values on a miss.
+    pub total_load_time: Duration,
+}
+
+impl CacheStatistics {
+    /// Returns the ratio of hits to all reads, or zero if there were no reads.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            reads => self.hits as f64 / reads as f64,
+        }
+    }
+
+    /// Returns the average time spent loading a value on a miss, or zero if there were no loads.
+    pub fn average_load_time(&self) -> Duration {
+        match self.loads {
+            0 => Duration::ZERO,
+            loads => Duration::from_secs_f64(self.total_load_time.as_secs_f64() / loads as f64),
+        }
+    }
+}
+
+/// Thread-safe counters a [`Cache`] backend records its statistics into.
+///
+/// [`Cache`]: crate::Cache
+#[derive(Debug, Default)]
+pub(crate) struct StatisticsCounter {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+    loads: AtomicU64,
+    load_nanos: AtomicU64,
+}
+
+impl StatisticsCounter {
+    pub(crate) fn record_hit(&self) {
+        self.record_reads(1, 0);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.record_reads(0, 1);
+    }
+
+    /// Records a batch of reads at once, which touches each counter once.
+    pub(crate) fn record_reads(&self, hits: u64, misses: u64) {
+        if hits > 0 {
+            self.hits.fetch_add(hits, Ordering::Relaxed);
+        }
+        if misses > 0 {
+            self.misses.fetch_add(misses, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_insert(&self) {
+        self.record_inserts(1);
+    }
+
+    pub(crate) fn record_inserts(&self, inserts: u64) {
+        self.inserts.fetch_add(inserts, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_eviction(&self) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_load(&self, load_time: Duration) {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        self.load_nanos.fetch_add(
+            u64::try_from(load_time.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Takes a snapshot of the counters completed by the current `entry_count` of the cache.
+    pub(crate) fn snapshot(&self, entry_count: u64) -> CacheStatistics {
+        CacheStatistics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entry_count,
+            loads: self.loads.load(Ordering::Relaxed),
+            total_load_time: Duration::from_nanos(self.load_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! A module dedicated to errors that occur while accessing EVM state.
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// A specialized [`Result`](std::result::Result) type for accessing EVM state.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An error that occurs while accessing EVM state.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying storage backend, such as a database, failed to perform the operation.
+    Backend(Box<dyn StdError + Send + Sync>),
+    /// The provided input, such as a genesis file, could not be read or decoded.
+    InvalidInput(Box<dyn StdError + Send + Sync>),
+    /// A Merkle proof does not prove what it claims against the given root.
+    InvalidProof(Box<dyn StdError + Send + Sync>),
+    /// The repository cannot perform the operation, such as enumerating storage of a database
+    /// that only looks slots up.
+    Unsupported(Box<dyn StdError + Send + Sync>),
+}
+
+impl Error {
+    /// Wraps an `error` of the underlying storage backend.
+    pub fn backend(error: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        Self::Backend(error.into())
+    }
+
+    /// Wraps an `error` of reading or decoding the provided input.
+    pub fn invalid_input(error: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        Self::InvalidInput(error.into())
+    }
+
+    /// Wraps an `error` of verifying a Merkle proof.
+    pub fn invalid_proof(error: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        Self::InvalidProof(error.into())
+    }
+
+    /// Wraps an `error` explaining why the repository cannot perform the operation.
+    pub fn unsupported(error: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        Self::Unsupported(error.into())
+    }
+
+    /// Takes an `error` shared by every caller of the same operation, such as a load of a value
+    /// missing in a cache. Unless it is the last reference, the error is wrapped while keeping its
+    /// kind.
+    pub(crate) fn shared(error: Arc<Error>) -> Self {
+        let error = match Arc::try_unwrap(error) {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        let kind = match error.as_ref() {
+            Self::Backend(_) => Self::Backend,
+            Self::InvalidInput(_) => Self::InvalidInput,
+            Self::InvalidProof(_) => Self::InvalidProof,
+            Self::Unsupported(_) => Self::Unsupported,
+        };
+
+        kind(Box::new(SharedError(error)))
+    }
+
+    fn inner(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        match self {
+            Self::Backend(error)
+            | Self::InvalidInput(error)
+            | Self::InvalidProof(error)
+            | Self::Unsupported(error) => error.as_ref(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Backend(error) => write!(f, "backend error: {error}"),
+            Self::InvalidInput(error) => write!(f, "invalid input: {error}"),
+            Self::InvalidProof(error) => write!(f, "invalid proof: {error}"),
+            Self::Unsupported(error) => write!(f, "unsupported: {error}"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(self.inner())
+    }
+}
+
+/// An [`Error`] shared by multiple callers that is displayed as the error it wraps.
+#[derive(Debug)]
+struct SharedError(Arc<Error>);
+
+impl Display for SharedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.0.inner(), f)
+    }
+}
+
+impl StdError for SharedError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.inner().source()
+    }
+}
@@ -1,22 +1,83 @@
 //! A module dedicated for EVM state entities and a read/write access trait.
 mod cached;
+mod changeset;
+mod codec;
 mod concurrent_in_memory;
+#[cfg(feature = "genesis")]
+mod genesis;
 mod in_memory;
+mod journaled;
+mod primitives;
+mod proof;
+#[cfg(feature = "redb")]
+mod redb;
 #[cfg(feature = "revm")]
 mod revm;
+#[cfg(feature = "revm")]
+mod revm_database;
+#[cfg(feature = "serde")]
+mod serialization;
+mod snapshot;
+#[cfg(test)]
+mod test_support;
+mod trie;
+mod versioned;
+mod write_back;
 
 pub use cached::*;
+pub use changeset::{AccountChange, Changeset, StorageChange};
+pub use codec::SlimAccount;
 pub use concurrent_in_memory::*;
+#[cfg(feature = "genesis")]
+pub use genesis::*;
 pub use in_memory::*;
+pub use journaled::{Checkpoint, JournaledEvmStateRepository};
+pub use primitives::{Address, ParseHexError, B256};
+pub use proof::{AccountProof, StorageProof};
+#[cfg(feature = "redb")]
+pub use redb::*;
 #[cfg(feature = "revm")]
 pub use revm::*;
+#[cfg(feature = "revm")]
+pub use revm_database::RevmDatabase;
+pub use snapshot::EvmStateSnapshot;
+pub use versioned::VersionedEvmStateRepository;
 
+use crate::error::{Error, Result};
 use primitive_types::U256;
+use std::sync::Arc;
 
-/// An Ethereum [address] uniquely identifies [`Account`].
+/// A contract [bytecode] executed by the EVM.
+///
+/// Bytecode is immutable and commonly shared between many accounts, hence it is cheap to clone.
 ///
-/// [address]: https://ethereum.org/en/glossary/#address
-pub type Address = [u8; 20];
+/// [bytecode]: https://ethereum.org/en/developers/docs/evm/opcodes/
+pub type Bytecode = Arc<[u8]>;
+
+/// Fails with [`Error::InvalidInput`] unless the `code_hash` is the Keccak-256 hash of the `code`,
+/// as required by [`EvmStateRepository::replace_code`].
+pub(crate) fn check_code_hash(code_hash: &B256, code: &Bytecode) -> Result<()> {
+    if *code_hash != B256::new(primitives::keccak256(code)) {
+        return Err(Error::invalid_input(format!(
+            "code hash {code_hash} does not match the code"
+        )));
+    }
+
+    Ok(())
+}
+
+/// A code hash of an [`Account`] without contract code, which is the Keccak-256 hash of no bytes.
+pub const EMPTY_CODE_HASH: B256 = B256::new([
+    0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7, 0x03, 0xc0,
+    0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+]);
+
+/// A root hash of an empty Merkle Patricia trie, which is a storage root of an [`Account`] without
+/// contract storage.
+pub const EMPTY_ROOT_HASH: B256 = B256::new([
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+]);
 
 /// An Ethereum [account] is an entity with an ether (ETH) balance that can send transactions.
 ///
@@ -24,15 +85,22 @@ pub type Address = [u8; 20];
 ///
 /// [account]: https://ethereum.org/en/developers/docs/accounts/
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "camelCase")
+)]
 pub struct Account {
+    #[cfg_attr(feature = "serde", serde(with = "serialization::quantity"))]
     nonce: u64,
+    #[cfg_attr(feature = "serde", serde(with = "serialization::quantity"))]
     balance: U256,
-    code_hash: U256,
-    storage_root: U256,
+    code_hash: B256,
+    storage_root: B256,
 }
 
 impl Account {
-    pub fn new(nonce: u64, balance: U256, code_hash: U256, storage_root: U256) -> Self {
+    pub fn new(nonce: u64, balance: U256, code_hash: B256, storage_root: B256) -> Self {
         Self {
             nonce,
             balance,
@@ -40,15 +108,467 @@ impl Account {
             storage_root,
         }
     }
+
+    /// Creates an account without nonce, balance, contract code and storage.
+    pub fn empty() -> Self {
+        Self::new(0, U256::zero(), EMPTY_CODE_HASH, EMPTY_ROOT_HASH)
+    }
+
+    /// Returns the number of transactions sent from the account, or contracts created by it.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Returns the balance of the account in wei.
+    pub fn balance(&self) -> U256 {
+        self.balance
+    }
+
+    /// Returns the hash of the contract code of the account.
+    pub fn code_hash(&self) -> B256 {
+        self.code_hash
+    }
+
+    /// Returns the root hash of the contract storage trie of the account.
+    pub fn storage_root(&self) -> B256 {
+        self.storage_root
+    }
+
+    /// Returns the account with the `nonce` replaced.
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Returns the account with the `balance` replaced.
+    pub fn with_balance(mut self, balance: U256) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Returns the account with the `code_hash` replaced.
+    pub fn with_code_hash(mut self, code_hash: B256) -> Self {
+        self.code_hash = code_hash;
+        self
+    }
+
+    /// Returns the account with the `storage_root` replaced.
+    pub fn with_storage_root(mut self, storage_root: B256) -> Self {
+        self.storage_root = storage_root;
+        self
+    }
+
+    /// Checks whether the account is [empty] as defined by EIP-161, i.e. it has zero nonce, zero
+    /// balance and no contract code.
+    ///
+    /// A zero code hash is treated as no contract code too, since it is used by backends that do
+    /// not hash code.
+    ///
+    /// [empty]: https://eips.ethereum.org/EIPS/eip-161
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0
+            && self.balance.is_zero()
+            && (self.code_hash == EMPTY_CODE_HASH || self.code_hash == B256::ZERO)
+    }
 }
 
 /// A trait for objects capable of accessing [EVM state].
 ///
+/// Every operation may fail when the underlying storage fails, in which case an [`Error`] is
+/// returned instead of a value. A failure is never reported as a missing value.
+///
 /// [EVM state]: https://ethereum.org/en/developers/docs/evm/#state
+/// [`Error`]: crate::Error
 pub trait EvmStateRepository {
     /// Tries to read [`Account`] and returns [`Some`] if it exists.
-    fn get(&self, address: &Address) -> Option<Account>;
+    fn get(&self, address: &Address) -> Result<Option<Account>>;
+
+    /// Tries to read [`Account`]s of all `addresses` and returns them in the same order, with
+    /// [`None`] in place of those that do not exist.
+    ///
+    /// Repositories that can read in batches more efficiently than one by one override the
+    /// default implementation, which reads them one by one.
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        addresses.iter().map(|address| self.get(address)).collect()
+    }
+
+    /// Writes `account` associated with the `address` regardless whether or not it exists.
+    fn replace(&mut self, address: Address, account: Account) -> Result<()>;
+
+    /// Writes all `accounts` associated with their addresses regardless whether or not they
+    /// exist.
+    ///
+    /// Repositories that can write in batches more efficiently than one by one override the
+    /// default implementation, which writes them one by one.
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        for (address, account) in accounts {
+            self.replace(address, account)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes [`Account`] associated with the `address` together with its contract storage, as
+    /// happens when the account self-destructs. Removing a non-existent account does nothing.
+    fn delete(&mut self, address: &Address) -> Result<()>;
+
+    /// Reads a value of the contract storage `slot` of an [`Account`] associated with the
+    /// `address`. A slot that has never been written reads as zero.
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256>;
 
+    /// Reads every contract storage slot of an [`Account`] associated with the `address` that
+    /// holds a non-zero value, in no particular order.
+    ///
+    /// Fails with [`Error::Unsupported`] if the repository can only look slots up one by one.
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>>;
+
+    /// Writes `value` into the contract storage `slot` of an [`Account`] associated with the
+    /// `address` regardless whether or not it exists.
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()>;
+
+    /// Tries to read contract [`Bytecode`] by its `code_hash` and returns [`Some`] if it exists.
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>>;
+
+    /// Writes contract `code` associated with the `code_hash` regardless whether or not it exists.
+    ///
+    /// Fails with [`Error::InvalidInput`] if the `code_hash` is not the Keccak-256 hash of the
+    /// `code`, and nothing is written then.
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()>;
+
+    /// Persists every write the repository has deferred so far. Repositories that persist each
+    /// write immediately have nothing to flush, which is the default.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A trait for [`EvmStateRepository`] objects that can be written from multiple threads
+/// concurrently.
+///
+/// Writes take a shared reference, so the repository can be shared behind an [`Arc`] without any
+/// external lock. An [`Arc`] of such repository, as well as a shared reference to it, is an
+/// [`EvmStateRepository`] itself.
+pub trait ConcurrentEvmStateRepository: EvmStateRepository + Send + Sync {
     /// Writes `account` associated with the `address` regardless whether or not it exists.
-    fn replace(&mut self, address: Address, account: Account);
+    fn replace(&self, address: Address, account: Account) -> Result<()>;
+
+    /// Writes all `accounts` associated with their addresses regardless whether or not they
+    /// exist.
+    fn replace_many(&self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        for (address, account) in accounts {
+            ConcurrentEvmStateRepository::replace(self, address, account)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes [`Account`] associated with the `address` together with its contract storage, as
+    /// happens when the account self-destructs. Removing a non-existent account does nothing.
+    fn delete(&self, address: &Address) -> Result<()>;
+
+    /// Writes `value` into the contract storage `slot` of an [`Account`] associated with the
+    /// `address` regardless whether or not it exists.
+    fn replace_storage(&self, address: Address, slot: U256, value: U256) -> Result<()>;
+
+    /// Writes contract `code` associated with the `code_hash` regardless whether or not it exists.
+    ///
+    /// Fails with [`Error::InvalidInput`] if the `code_hash` is not the Keccak-256 hash of the
+    /// `code`, and nothing is written then.
+    fn replace_code(&self, code_hash: B256, code: Bytecode) -> Result<()>;
+
+    /// Persists every write the repository has deferred so far. Repositories that persist each
+    /// write immediately have nothing to flush, which is the default.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<R: ConcurrentEvmStateRepository + ?Sized> EvmStateRepository for Arc<R> {
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        self.as_ref().get(address)
+    }
+
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        self.as_ref().get_many(addresses)
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        ConcurrentEvmStateRepository::replace(self.as_ref(), address, account)
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_many(self.as_ref(), accounts)
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        ConcurrentEvmStateRepository::delete(self.as_ref(), address)
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        self.as_ref().get_storage(address, slot)
+    }
+
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        self.as_ref().get_all_storage(address)
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_storage(self.as_ref(), address, slot, value)
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        self.as_ref().get_code(code_hash)
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_code(self.as_ref(), code_hash, code)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        ConcurrentEvmStateRepository::flush(self.as_ref())
+    }
+}
+
+impl<R: ConcurrentEvmStateRepository + ?Sized> ConcurrentEvmStateRepository for Arc<R> {
+    fn replace(&self, address: Address, account: Account) -> Result<()> {
+        ConcurrentEvmStateRepository::replace(self.as_ref(), address, account)
+    }
+
+    fn replace_many(&self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_many(self.as_ref(), accounts)
+    }
+
+    fn delete(&self, address: &Address) -> Result<()> {
+        ConcurrentEvmStateRepository::delete(self.as_ref(), address)
+    }
+
+    fn replace_storage(&self, address: Address, slot: U256, value: U256) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_storage(self.as_ref(), address, slot, value)
+    }
+
+    fn replace_code(&self, code_hash: B256, code: Bytecode) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_code(self.as_ref(), code_hash, code)
+    }
+
+    fn flush(&self) -> Result<()> {
+        ConcurrentEvmStateRepository::flush(self.as_ref())
+    }
+}
+
+impl<R: ConcurrentEvmStateRepository + ?Sized> EvmStateRepository for &R {
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        (**self).get(address)
+    }
+
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        (**self).get_many(addresses)
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        ConcurrentEvmStateRepository::replace(*self, address, account)
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_many(*self, accounts)
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        ConcurrentEvmStateRepository::delete(*self, address)
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        (**self).get_storage(address, slot)
+    }
+
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        (**self).get_all_storage(address)
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_storage(*self, address, slot, value)
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        (**self).get_code(code_hash)
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_code(*self, code_hash, code)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        ConcurrentEvmStateRepository::flush(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_account_is_empty() {
+        let account = Account::empty();
+
+        assert!(account.is_empty(), "Empty account is not empty");
+        assert_eq!(EMPTY_CODE_HASH, account.code_hash());
+        assert_eq!(EMPTY_ROOT_HASH, account.storage_root());
+    }
+
+    #[test]
+    fn test_account_with_nonce_or_balance_or_code_is_not_empty() {
+        let with_nonce = Account::empty().with_nonce(1);
+        let with_balance = Account::empty().with_balance(U256::one());
+        let with_code = Account::empty().with_code_hash(B256::from(U256::one()));
+
+        assert!(!with_nonce.is_empty(), "Account with nonce is empty");
+        assert!(!with_balance.is_empty(), "Account with balance is empty");
+        assert!(!with_code.is_empty(), "Account with code is empty");
+    }
+
+    #[test]
+    fn test_account_with_zero_code_hash_is_empty() {
+        let account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
+
+        assert!(account.is_empty(), "Account without code hash is not empty");
+    }
+
+    #[test]
+    fn test_account_fields_are_read_back() {
+        let account = Account::empty()
+            .with_nonce(1)
+            .with_balance(U256::from(2))
+            .with_code_hash(B256::from(U256::from(3)))
+            .with_storage_root(B256::from(U256::from(4)));
+
+        assert_eq!(1, account.nonce());
+        assert_eq!(U256::from(2), account.balance());
+        assert_eq!(B256::from(U256::from(3)), account.code_hash());
+        assert_eq!(B256::from(U256::from(4)), account.storage_root());
+    }
+
+    #[test]
+    fn test_well_known_hashes_match_their_hex_representation() {
+        let empty_code_hash = "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+            .parse::<B256>()
+            .unwrap();
+        let empty_root_hash = "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+            .parse::<B256>()
+            .unwrap();
+
+        assert_eq!(empty_code_hash, EMPTY_CODE_HASH);
+        assert_eq!(empty_root_hash, EMPTY_ROOT_HASH);
+    }
+
+    /// Writes a storage slot of an account that does not exist and checks that only the slot,
+    /// not the account, is found afterwards.
+    fn assert_storage_write_does_not_create_account(mut repository: impl EvmStateRepository) {
+        let address = test_support::address(1);
+
+        repository
+            .replace_storage(address, U256::one(), U256::from(7))
+            .unwrap();
+
+        assert_eq!(None, repository.get(&address).unwrap());
+        assert_eq!(
+            U256::from(7),
+            repository.get_storage(&address, &U256::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_storage_write_does_not_create_account_in_any_backend() {
+        assert_storage_write_does_not_create_account(InMemoryEvmStateRepository::default());
+        assert_storage_write_does_not_create_account(
+            ConcurrentInMemoryEvmStateRepository::default(),
+        );
+
+        #[cfg(feature = "revm")]
+        assert_storage_write_does_not_create_account(RevmStateRepository::new(
+            ::revm::InMemoryDB::default(),
+        ));
+
+        #[cfg(feature = "redb")]
+        {
+            let directory = tempfile::tempdir().unwrap();
+            let repository =
+                RedbEvmStateRepository::open(directory.path().join("state.redb")).unwrap();
+            assert_storage_write_does_not_create_account(&repository);
+        }
+    }
+
+    /// Writes code by a hash that does not match it and checks that it is rejected and not
+    /// written.
+    fn assert_code_with_mismatching_hash_is_rejected(mut repository: impl EvmStateRepository) {
+        let code_hash = B256::new([1u8; 32]);
+
+        let result = repository.replace_code(code_hash, Bytecode::from(vec![0x60, 0x00]));
+
+        assert!(
+            matches!(result, Err(Error::InvalidInput(_))),
+            "Mismatching code hash accepted"
+        );
+        assert_eq!(None, repository.get_code(&code_hash).unwrap());
+    }
+
+    #[test]
+    fn test_code_with_mismatching_hash_is_rejected_by_any_backend() {
+        assert_code_with_mismatching_hash_is_rejected(InMemoryEvmStateRepository::default());
+        assert_code_with_mismatching_hash_is_rejected(
+            ConcurrentInMemoryEvmStateRepository::default(),
+        );
+
+        #[cfg(feature = "revm")]
+        assert_code_with_mismatching_hash_is_rejected(RevmStateRepository::new(
+            ::revm::InMemoryDB::default(),
+        ));
+
+        #[cfg(feature = "redb")]
+        {
+            let directory = tempfile::tempdir().unwrap();
+            let repository =
+                RedbEvmStateRepository::open(directory.path().join("state.redb")).unwrap();
+            assert_code_with_mismatching_hash_is_rejected(&repository);
+        }
+    }
+
+    /// Writes storage of two accounts and checks that only the non-zero slots of the first one
+    /// are read back.
+    fn assert_all_storage_of_account_is_read(mut repository: impl EvmStateRepository) {
+        for (slot, value) in [(1, 7), (2, 8), (3, 0)] {
+            repository
+                .replace_storage(
+                    test_support::address(1),
+                    U256::from(slot),
+                    U256::from(value),
+                )
+                .unwrap();
+        }
+        repository
+            .replace_storage(test_support::address(2), U256::one(), U256::from(9))
+            .unwrap();
+
+        let mut actual_storage = repository
+            .get_all_storage(&test_support::address(1))
+            .unwrap();
+        actual_storage.sort();
+
+        assert_eq!(
+            vec![(U256::one(), U256::from(7)), (U256::from(2), U256::from(8))],
+            actual_storage
+        );
+    }
+
+    #[test]
+    fn test_all_storage_of_account_is_read_from_any_backend() {
+        assert_all_storage_of_account_is_read(InMemoryEvmStateRepository::default());
+        assert_all_storage_of_account_is_read(ConcurrentInMemoryEvmStateRepository::default());
+
+        #[cfg(feature = "redb")]
+        {
+            let directory = tempfile::tempdir().unwrap();
+            let repository =
+                RedbEvmStateRepository::open(directory.path().join("state.redb")).unwrap();
+            assert_all_storage_of_account_is_read(&repository);
+        }
+    }
 }
@@ -2,34 +2,318 @@
 ///
 /// Wraps a different implementation of [`EvmStateRepository`] and adds a caching layer on top
 /// of it. Primarily, the data is read from cache.
-use crate::cache::Cache;
-use crate::evm_state::{Account, Address, EvmStateRepository};
+use crate::cache::{Cache, CacheStatistics, NoopCache};
+use crate::error::Result;
+use crate::evm_state::write_back::WriteBackRepository;
+use crate::evm_state::{
+    Account, Address, Bytecode, Changeset, ConcurrentEvmStateRepository, EvmStateRepository, B256,
+};
+use primitive_types::U256;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// An [`EvmStateRepository`] that uses a different repository to access the data and adds a layer
 /// of [`Cache`] on top of it.
 ///
 /// This implementation is capable of working while primarily keeping the cache updated and
 /// accessed first, before the underlying repository.  
-pub struct CachedEvmStateRepository<InnerRepository: EvmStateRepository, C: Cache<Address, Account>>
-{
+///
+/// Contract storage slots are kept in a separate [`Cache`] keyed by address and slot. Unless one
+/// is provided by [`CachedEvmStateRepository::with_storage_cache`], storage is not cached.
+///
+/// Contract code is kept in a separate [`Cache`] keyed by code hash as well. Bytecode is large
+/// and shared between accounts, so a cache weighted by size such as the one created by
+/// [`CacheBuilder::build_weighted`](crate::CacheBuilder::build_weighted) suits it best. Unless one
+/// is provided by [`CachedEvmStateRepository::with_code_cache`], code is not cached.
+///
+/// Addresses without an account are not cached by default, so each lookup of such address reaches
+/// the underlying repository. Caching absence is opted into by providing a negative cache by
+/// [`CachedEvmStateRepository::with_negative_cache`].
+///
+/// Writes go through to the underlying repository by default. In the write-back mode enabled by
+/// [`CachedEvmStateRepository::with_write_back`], they are deferred instead and persisted in a
+/// batch by [`EvmStateRepository::flush`], once the number of deferred writes reaches the given
+/// capacity, once a write evicts a dirty account from a cache that reports its evictions, or when
+/// the repository is dropped. Deferred writes are kept aside from the caches as well, so they are
+/// never lost by an eviction before being persisted.
+pub struct CachedEvmStateRepository<
+    InnerRepository: EvmStateRepository,
+    C: Cache<Address, Account>,
+    S: Cache<(Address, U256), U256> = NoopCache,
+    B: Cache<B256, Bytecode> = NoopCache,
+    N: Cache<Address, ()> = NoopCache,
+> {
     cache: C,
-    inner: InnerRepository,
+    storage_cache: S,
+    code_cache: B,
+    negative_cache: N,
+    inner: WriteBackRepository<InnerRepository>,
+    generations: WriteGenerations,
+}
+
+/// Counters of writes of addresses hashed into a fixed number of stripes.
+///
+/// A load that reads the underlying repository before a concurrent write but caches the value
+/// after that write has invalidated the cache would leave a stale value cached. Such load compares
+/// the counter of its address before and after, and discards what it has cached on a change.
+#[derive(Debug)]
+struct WriteGenerations(Box<[AtomicU64]>);
+
+impl Default for WriteGenerations {
+    fn default() -> Self {
+        Self((0..64).map(|_| AtomicU64::new(0)).collect())
+    }
+}
+
+impl WriteGenerations {
+    fn stripe(&self, address: &Address) -> &AtomicU64 {
+        let mut hasher = DefaultHasher::new();
+        address.hash(&mut hasher);
+        &self.0[hasher.finish() as usize % self.0.len()]
+    }
+
+    fn current(&self, address: &Address) -> u64 {
+        self.stripe(address).load(Ordering::SeqCst)
+    }
+
+    fn advance(&self, address: &Address) {
+        self.stripe(address).fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A snapshot of [`CacheStatistics`] of every cache used by [`CachedEvmStateRepository`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CachedEvmStateStatistics {
+    /// Statistics of the cache of accounts.
+    pub accounts: CacheStatistics,
+    /// Statistics of the cache of contract storage slots.
+    pub storage: CacheStatistics,
+    /// Statistics of the cache of contract code.
+    pub code: CacheStatistics,
+    /// Statistics of the cache of addresses without an account.
+    pub negative: CacheStatistics,
+}
+
+impl<
+        InnerRepository: EvmStateRepository,
+        C: Cache<Address, Account>,
+        S: Cache<(Address, U256), U256>,
+        B: Cache<B256, Bytecode>,
+        N: Cache<Address, ()>,
+    > EvmStateRepository for CachedEvmStateRepository<InnerRepository, C, S, B, N>
+{
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        let generation = self.generations.current(address);
+        let mut loaded = false;
+        let account = self.cache.try_read_or_write_with(*address, || {
+            if self.negative_cache.contains(address) {
+                return Ok(None);
+            }
+
+            loaded = true;
+            let account = self.inner.get(address)?;
+            if account.is_none() {
+                self.negative_cache.write(*address, ());
+            }
+
+            Ok(account)
+        })?;
+
+        if loaded && self.generations.current(address) != generation {
+            self.cache.invalidate(address);
+            self.negative_cache.invalidate(address);
+        }
+
+        Ok(account)
+    }
+
+    /// Reads cached accounts and loads only the missing ones from the underlying repository in a
+    /// single batch, skipping addresses cached as absent.
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        let mut accounts = self.cache.read_many(addresses);
+
+        let mut misses = Vec::new();
+        let mut seen = HashSet::new();
+        for (address, account) in addresses.iter().zip(&accounts) {
+            if account.is_none() && seen.insert(*address) && !self.negative_cache.contains(address)
+            {
+                misses.push(*address);
+            }
+        }
+        if misses.is_empty() {
+            return Ok(accounts);
+        }
+
+        let generations: Vec<_> = misses
+            .iter()
+            .map(|address| self.generations.current(address))
+            .collect();
+        let loaded_accounts: HashMap<_, _> = misses
+            .iter()
+            .copied()
+            .zip(self.inner.get_many(&misses)?)
+            .collect();
+
+        for (address, account) in addresses.iter().zip(&mut accounts) {
+            if account.is_none() {
+                *account = loaded_accounts.get(address).cloned().flatten();
+            }
+        }
+
+        let mut found_accounts = Vec::with_capacity(loaded_accounts.len());
+        for (address, account) in loaded_accounts {
+            match account {
+                Some(account) => found_accounts.push((address, account)),
+                None => self.negative_cache.write(address, ()),
+            }
+        }
+        self.cache.write_many(found_accounts);
+
+        for (address, generation) in misses.iter().zip(generations) {
+            if self.generations.current(address) != generation {
+                self.cache.invalidate(address);
+                self.negative_cache.invalidate(address);
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /// Writes the `account` and caches it. In the write-back mode, deferred writes are flushed if
+    /// caching it evicts a dirty account. A failure of such flush is returned although the write
+    /// itself is deferred, and the next flush retries it.
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        self.inner.replace(address, account.clone())?;
+        let evicted = self.cache_replaced(address, account);
+
+        self.inner.flush_evicted(&evicted)
+    }
+
+    /// Writes the `accounts` and caches them, flushing deferred writes the same way as
+    /// [`EvmStateRepository::replace`] does.
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        self.inner.replace_many(accounts.clone())?;
+        let evicted = self.cache_replaced_many(accounts);
+
+        self.inner.flush_evicted(&evicted)
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        self.inner.delete(address)?;
+        self.cache_deleted(address);
+
+        Ok(())
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        let generation = self.generations.current(address);
+        let mut loaded = false;
+        let value = self
+            .storage_cache
+            .try_read_or_write_with((*address, *slot), || {
+                loaded = true;
+                self.inner.get_storage(address, slot).map(Some)
+            })?;
+
+        if loaded && self.generations.current(address) != generation {
+            self.storage_cache.invalidate(&(*address, *slot));
+        }
+
+        Ok(value.unwrap_or_default())
+    }
+
+    /// Reads the slots from the inner repository, as the storage cache holds only some of them.
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        self.inner.get_all_storage(address)
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        self.inner.replace_storage(address, slot, value)?;
+        self.storage_cache.write((address, slot), value);
+
+        Ok(())
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        self.code_cache
+            .try_read_or_write_with(*code_hash, || self.inner.get_code(code_hash))
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        self.inner.replace_code(code_hash, code.clone())?;
+        self.code_cache.write(code_hash, code);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
 }
 
-impl<InnerRepository: EvmStateRepository, C: Cache<Address, Account>> EvmStateRepository
-    for CachedEvmStateRepository<InnerRepository, C>
+impl<
+        InnerRepository: ConcurrentEvmStateRepository,
+        C: Cache<Address, Account> + Send + Sync,
+        S: Cache<(Address, U256), U256> + Send + Sync,
+        B: Cache<B256, Bytecode> + Send + Sync,
+        N: Cache<Address, ()> + Send + Sync,
+    > ConcurrentEvmStateRepository for CachedEvmStateRepository<InnerRepository, C, S, B, N>
 {
-    fn get(&self, address: &Address) -> Option<Account> {
-        if !self.cache.contains(address) {
-            self.cache.write(*address, self.inner.get(address)?.clone());
+    /// Writes the `account` and discards the cached one, rather than caching the written one.
+    /// Concurrent writes of the same `address` could otherwise leave the account cached that was
+    /// not written last.
+    fn replace(&self, address: Address, account: Account) -> Result<()> {
+        ConcurrentEvmStateRepository::replace(&self.inner, address, account)?;
+        self.generations.advance(&address);
+        self.cache.invalidate(&address);
+        self.negative_cache.invalidate(&address);
+
+        Ok(())
+    }
+
+    fn replace_many(&self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        let addresses: Vec<_> = accounts
+            .iter()
+            .map(|(address, _account)| *address)
+            .collect();
+        ConcurrentEvmStateRepository::replace_many(&self.inner, accounts)?;
+
+        for address in &addresses {
+            self.generations.advance(address);
+            self.cache.invalidate(address);
+            self.negative_cache.invalidate(address);
         }
 
-        self.cache.read(address)
+        Ok(())
+    }
+
+    fn delete(&self, address: &Address) -> Result<()> {
+        ConcurrentEvmStateRepository::delete(&self.inner, address)?;
+        self.generations.advance(address);
+        self.invalidate(address);
+
+        Ok(())
+    }
+
+    fn replace_storage(&self, address: Address, slot: U256, value: U256) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_storage(&self.inner, address, slot, value)?;
+        self.generations.advance(&address);
+        self.storage_cache.invalidate(&(address, slot));
+
+        Ok(())
     }
 
-    fn replace(&mut self, address: Address, account: Account) {
-        self.inner.replace(address, account.clone());
-        self.cache.write(address, account);
+    fn replace_code(&self, code_hash: B256, code: Bytecode) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_code(&self.inner, code_hash, code.clone())?;
+        self.code_cache.write(code_hash, code);
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        ConcurrentEvmStateRepository::flush(&self.inner)
     }
 }
 
@@ -38,8 +322,177 @@ impl<InnerRepository: EvmStateRepository, C: Cache<Address, Account>>
 {
     pub fn new(repository: InnerRepository, cache: C) -> Self {
         Self {
-            inner: repository,
+            inner: WriteBackRepository::write_through(repository),
             cache,
+            storage_cache: NoopCache,
+            code_cache: NoopCache,
+            negative_cache: NoopCache,
+            generations: WriteGenerations::default(),
+        }
+    }
+}
+
+impl<
+        InnerRepository: EvmStateRepository,
+        C: Cache<Address, Account>,
+        S: Cache<(Address, U256), U256>,
+        B: Cache<B256, Bytecode>,
+        N: Cache<Address, ()>,
+    > CachedEvmStateRepository<InnerRepository, C, S, B, N>
+{
+    /// Discards cached [`Account`] associated with the `address` together with its cached
+    /// contract storage slots.
+    ///
+    /// Subsequent reads load them from the underlying repository again. Useful when the
+    /// underlying repository was written to bypassing this cache.
+    pub fn invalidate(&self, address: &Address) {
+        self.cache.invalidate(address);
+        self.negative_cache.invalidate(address);
+        let address = *address;
+        self.storage_cache
+            .invalidate_if(Box::new(move |(slot_address, _slot), _value| {
+                *slot_address == address
+            }));
+    }
+
+    /// Discards every cached [`Account`] for which the `predicate` returns `true`.
+    ///
+    /// Contract storage slots of such accounts remain cached; use
+    /// [`CachedEvmStateRepository::invalidate`] to discard them as well.
+    pub fn invalidate_if(
+        &self,
+        predicate: impl Fn(&Address, &Account) -> bool + Send + Sync + 'static,
+    ) {
+        self.cache.invalidate_if(Box::new(predicate));
+    }
+
+    /// Discards every cached entry, including contract storage slots and code, e.g. after a
+    /// chain reorganization.
+    pub fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+        self.storage_cache.invalidate_all();
+        self.code_cache.invalidate_all();
+        self.negative_cache.invalidate_all();
+    }
+
+    /// Takes a snapshot of statistics of every cache, e.g. to tune their capacity.
+    pub fn statistics(&self) -> CachedEvmStateStatistics {
+        CachedEvmStateStatistics {
+            accounts: self.cache.statistics(),
+            storage: self.storage_cache.statistics(),
+            code: self.code_cache.statistics(),
+            negative: self.negative_cache.statistics(),
+        }
+    }
+
+    /// Returns changes of accounts and storage deferred in the write-back mode that are yet to be
+    /// flushed, compared with the underlying repository. Nothing is deferred in the write-through
+    /// mode, so the changeset is empty.
+    ///
+    /// Taken right before [`EvmStateRepository::flush`], it holds the changes the flush makes.
+    pub fn changeset(&self) -> Result<Changeset> {
+        self.inner.changeset()
+    }
+
+    /// Returns `true` if there are writes deferred in the write-back mode that are yet to be
+    /// flushed.
+    pub fn is_dirty(&self) -> bool {
+        self.inner.is_dirty()
+    }
+
+    /// Caches the written `account` and returns addresses of accounts evicted to make room for it,
+    /// which only the write-back mode needs to know.
+    fn cache_replaced(&self, address: Address, account: Account) -> Vec<Address> {
+        self.negative_cache.invalidate(&address);
+        if !self.inner.is_write_back() {
+            self.cache.write(address, account);
+            return Vec::new();
+        }
+
+        self.cache
+            .write_evicting(address, account)
+            .into_iter()
+            .map(|(address, _account)| address)
+            .collect()
+    }
+
+    fn cache_replaced_many(&self, accounts: Vec<(Address, Account)>) -> Vec<Address> {
+        for (address, _account) in &accounts {
+            self.negative_cache.invalidate(address);
+        }
+        if !self.inner.is_write_back() {
+            self.cache.write_many(accounts);
+            return Vec::new();
+        }
+
+        accounts
+            .into_iter()
+            .flat_map(|(address, account)| self.cache.write_evicting(address, account))
+            .map(|(address, _account)| address)
+            .collect()
+    }
+
+    fn cache_deleted(&self, address: &Address) {
+        self.invalidate(address);
+        self.negative_cache.write(*address, ());
+    }
+
+    /// Enables the write-back mode, which defers writes to the underlying repository until they
+    /// are flushed. They are flushed in a batch once `capacity` of them accumulate, and once a
+    /// write evicts a dirty account from the account cache, if the cache reports its evictions by
+    /// [`Cache::write_evicting`] like [`LruCache`](crate::LruCache) does. Writes through
+    /// [`ConcurrentEvmStateRepository`] do not cache written accounts, so they evict none.
+    ///
+    /// Errors of the flush on drop cannot be returned and are only logged, so the repository
+    /// should be flushed explicitly by [`EvmStateRepository::flush`] before it is dropped, for
+    /// which [`Self::is_dirty`] can be checked.
+    pub fn with_write_back(mut self, capacity: usize) -> Self {
+        self.inner = self.inner.with_write_back(capacity);
+        self
+    }
+
+    /// Sets the `storage_cache` that holds contract storage slots keyed by address and slot.
+    pub fn with_storage_cache<T: Cache<(Address, U256), U256>>(
+        self,
+        storage_cache: T,
+    ) -> CachedEvmStateRepository<InnerRepository, C, T, B, N> {
+        CachedEvmStateRepository {
+            cache: self.cache,
+            storage_cache,
+            code_cache: self.code_cache,
+            negative_cache: self.negative_cache,
+            inner: self.inner,
+            generations: self.generations,
+        }
+    }
+
+    /// Sets the `code_cache` that holds contract bytecode keyed by code hash.
+    pub fn with_code_cache<T: Cache<B256, Bytecode>>(
+        self,
+        code_cache: T,
+    ) -> CachedEvmStateRepository<InnerRepository, C, S, T, N> {
+        CachedEvmStateRepository {
+            cache: self.cache,
+            storage_cache: self.storage_cache,
+            code_cache,
+            negative_cache: self.negative_cache,
+            inner: self.inner,
+            generations: self.generations,
+        }
+    }
+
+    /// Sets the `negative_cache` that holds addresses known to have no [`Account`].
+    pub fn with_negative_cache<T: Cache<Address, ()>>(
+        self,
+        negative_cache: T,
+    ) -> CachedEvmStateRepository<InnerRepository, C, S, B, T> {
+        CachedEvmStateRepository {
+            cache: self.cache,
+            storage_cache: self.storage_cache,
+            code_cache: self.code_cache,
+            negative_cache,
+            inner: self.inner,
+            generations: self.generations,
         }
     }
 }
@@ -47,9 +500,17 @@ impl<InnerRepository: EvmStateRepository, C: Cache<Address, Account>>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::InMemoryEvmStateRepository;
-    use primitive_types::U256;
-    use std::sync::RwLock;
+    use crate::evm_state::primitives::keccak256;
+    use crate::evm_state::test_support::{account, address};
+    use crate::{
+        CacheBuilder, ConcurrentInMemoryEvmStateRepository, Error, EvictionPolicy,
+        InMemoryEvmStateRepository, Predicate,
+    };
+    use moka::sync::Cache as Moka;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier, Mutex, RwLock};
+    use std::thread;
+    use std::time::Duration;
 
     struct DummyCache(RwLock<Account>);
 
@@ -59,6 +520,12 @@ mod tests {
         }
 
         fn write(&self, _key: Address, _value: Account) {}
+
+        fn invalidate(&self, _key: &Address) {}
+
+        fn invalidate_if(&self, _predicate: Predicate<Address, Account>) {}
+
+        fn invalidate_all(&self) {}
     }
 
     struct EmptyCache(RwLock<Option<Account>>);
@@ -71,26 +538,245 @@ mod tests {
         fn write(&self, _key: Address, value: Account) {
             self.0.write().unwrap().replace(value);
         }
+
+        fn invalidate(&self, _key: &Address) {
+            self.0.write().unwrap().take();
+        }
+
+        fn invalidate_if(&self, _predicate: Predicate<Address, Account>) {}
+
+        fn invalidate_all(&self) {
+            self.0.write().unwrap().take();
+        }
     }
 
     struct NoopEvmRepository;
 
     impl EvmStateRepository for NoopEvmRepository {
-        fn get(&self, _address: &Address) -> Option<Account> {
-            None
+        fn get(&self, _address: &Address) -> Result<Option<Account>> {
+            Ok(None)
+        }
+
+        fn replace(&mut self, _address: Address, _account: Account) -> Result<()> {
+            Ok(())
+        }
+
+        fn delete(&mut self, _address: &Address) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_storage(&self, _address: &Address, _slot: &U256) -> Result<U256> {
+            Ok(U256::zero())
+        }
+
+        fn get_all_storage(&self, _address: &Address) -> Result<Vec<(U256, U256)>> {
+            Ok(Vec::new())
+        }
+
+        fn replace_storage(&mut self, _address: Address, _slot: U256, _value: U256) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_code(&self, _code_hash: &B256) -> Result<Option<Bytecode>> {
+            Ok(None)
+        }
+
+        fn replace_code(&mut self, _code_hash: B256, _code: Bytecode) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct SlowCountingEvmRepository {
+        loads: AtomicUsize,
+        failing: bool,
+    }
+
+    impl SlowCountingEvmRepository {
+        fn failing() -> Self {
+            Self {
+                failing: true,
+                ..Self::default()
+            }
+        }
+
+        fn load(&self) -> Result<()> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+
+            match self.failing {
+                true => Err(Error::backend("Database is unavailable")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl EvmStateRepository for SlowCountingEvmRepository {
+        fn get(&self, _address: &Address) -> Result<Option<Account>> {
+            self.load()?;
+
+            Ok(Some(Account::new(4, U256::zero(), B256::ZERO, B256::ZERO)))
+        }
+
+        fn replace(&mut self, _address: Address, _account: Account) -> Result<()> {
+            Ok(())
+        }
+
+        fn delete(&mut self, _address: &Address) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_storage(&self, _address: &Address, _slot: &U256) -> Result<U256> {
+            self.load()?;
+
+            Ok(U256::one())
+        }
+
+        fn get_all_storage(&self, _address: &Address) -> Result<Vec<(U256, U256)>> {
+            Ok(Vec::new())
+        }
+
+        fn replace_storage(&mut self, _address: Address, _slot: U256, _value: U256) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_code(&self, _code_hash: &B256) -> Result<Option<Bytecode>> {
+            Ok(None)
+        }
+
+        fn replace_code(&mut self, _code_hash: B256, _code: Bytecode) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Holds the first read or write of an account after performing it, until the test lets it
+    /// finish.
+    #[derive(Default)]
+    struct GatedEvmRepository {
+        inner: ConcurrentInMemoryEvmStateRepository,
+        gate: Mutex<Option<(Arc<Barrier>, Arc<Barrier>)>>,
+    }
+
+    impl GatedEvmRepository {
+        /// Returns barriers that are passed once the account has been accessed and once the test
+        /// lets the access finish.
+        fn gate(&self) -> (Arc<Barrier>, Arc<Barrier>) {
+            let barriers = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+            self.gate.lock().unwrap().replace(barriers.clone());
+            barriers
+        }
+
+        fn pass_gate(&self) {
+            let gate = self.gate.lock().unwrap().take();
+            if let Some((accessed, released)) = gate {
+                accessed.wait();
+                released.wait();
+            }
+        }
+    }
+
+    impl EvmStateRepository for GatedEvmRepository {
+        fn get(&self, address: &Address) -> Result<Option<Account>> {
+            let account = self.inner.get(address);
+            self.pass_gate();
+
+            account
+        }
+
+        fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+            ConcurrentEvmStateRepository::replace(self, address, account)
+        }
+
+        fn delete(&mut self, address: &Address) -> Result<()> {
+            ConcurrentEvmStateRepository::delete(self, address)
+        }
+
+        fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+            self.inner.get_storage(address, slot)
+        }
+
+        fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+            self.inner.get_all_storage(address)
+        }
+
+        fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+            ConcurrentEvmStateRepository::replace_storage(self, address, slot, value)
+        }
+
+        fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+            self.inner.get_code(code_hash)
+        }
+
+        fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+            ConcurrentEvmStateRepository::replace_code(self, code_hash, code)
+        }
+    }
+
+    impl ConcurrentEvmStateRepository for GatedEvmRepository {
+        fn replace(&self, address: Address, account: Account) -> Result<()> {
+            ConcurrentEvmStateRepository::replace(&self.inner, address, account)?;
+            self.pass_gate();
+
+            Ok(())
+        }
+
+        fn delete(&self, address: &Address) -> Result<()> {
+            ConcurrentEvmStateRepository::delete(&self.inner, address)
+        }
+
+        fn replace_storage(&self, address: Address, slot: U256, value: U256) -> Result<()> {
+            ConcurrentEvmStateRepository::replace_storage(&self.inner, address, slot, value)
+        }
+
+        fn replace_code(&self, code_hash: B256, code: Bytecode) -> Result<()> {
+            ConcurrentEvmStateRepository::replace_code(&self.inner, code_hash, code)
+        }
+    }
+
+    struct FailingEvmRepository;
+
+    impl EvmStateRepository for FailingEvmRepository {
+        fn get(&self, _address: &Address) -> Result<Option<Account>> {
+            Err(Error::backend("Database is unavailable"))
+        }
+
+        fn replace(&mut self, _address: Address, _account: Account) -> Result<()> {
+            Err(Error::backend("Database is unavailable"))
+        }
+
+        fn delete(&mut self, _address: &Address) -> Result<()> {
+            Err(Error::backend("Database is unavailable"))
+        }
+
+        fn get_storage(&self, _address: &Address, _slot: &U256) -> Result<U256> {
+            Err(Error::backend("Database is unavailable"))
+        }
+
+        fn get_all_storage(&self, _address: &Address) -> Result<Vec<(U256, U256)>> {
+            Err(Error::backend("Database is unavailable"))
+        }
+
+        fn replace_storage(&mut self, _address: Address, _slot: U256, _value: U256) -> Result<()> {
+            Err(Error::backend("Database is unavailable"))
+        }
+
+        fn get_code(&self, _code_hash: &B256) -> Result<Option<Bytecode>> {
+            Err(Error::backend("Database is unavailable"))
         }
 
-        fn replace(&mut self, _address: Address, _account: Account) {}
+        fn replace_code(&mut self, _code_hash: B256, _code: Bytecode) -> Result<()> {
+            Err(Error::backend("Database is unavailable"))
+        }
     }
 
     #[test]
     fn test_account_is_primarily_taken_from_cache() {
-        let expected_account = Account::new(4, U256::zero(), U256::zero(), U256::zero());
+        let expected_account = Account::new(4, U256::zero(), B256::ZERO, B256::ZERO);
         let repository = NoopEvmRepository;
         let cache = DummyCache(RwLock::new(expected_account.clone()));
         let repository = CachedEvmStateRepository::new(repository, cache);
 
-        let actual_account = repository.get(&[0u8; 20]);
+        let actual_account = repository.get(&Address::ZERO).unwrap();
 
         assert!(actual_account.is_some(), "Account not hit in cache");
 
@@ -101,13 +787,15 @@ mod tests {
 
     #[test]
     fn test_account_is_loaded_from_repository_when_cache_misses() {
-        let expected_account = Account::new(4, U256::zero(), U256::zero(), U256::zero());
+        let expected_account = Account::new(4, U256::zero(), B256::ZERO, B256::ZERO);
         let mut repository = InMemoryEvmStateRepository::default();
-        repository.replace([0u8; 20], expected_account.clone());
+        repository
+            .replace(Address::ZERO, expected_account.clone())
+            .unwrap();
         let cache = EmptyCache(RwLock::new(None));
         let repository = CachedEvmStateRepository::new(repository, cache);
 
-        let actual_account = repository.get(&[0u8; 20]);
+        let actual_account = repository.get(&Address::ZERO).unwrap();
 
         assert!(
             actual_account.is_some(),
@@ -118,4 +806,792 @@ mod tests {
 
         assert_eq!(expected_account, actual_account);
     }
+
+    #[test]
+    fn test_storage_slot_is_loaded_from_repository_and_cached_when_cache_misses() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+        let storage_cache = Moka::new(10);
+        let repository = CachedEvmStateRepository::new(repository, EmptyCache(RwLock::new(None)))
+            .with_storage_cache(storage_cache.clone());
+
+        let actual_value = repository
+            .get_storage(&Address::ZERO, &U256::one())
+            .unwrap();
+
+        assert_eq!(U256::from(7), actual_value);
+        assert_eq!(
+            Some(U256::from(7)),
+            storage_cache.read(&(Address::ZERO, U256::one())),
+            "Storage slot not cached"
+        );
+    }
+
+    #[test]
+    fn test_code_is_loaded_from_repository_and_cached_when_cache_misses() {
+        let code_hash = B256::new(keccak256([0x60, 0x00]));
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace_code(code_hash, Bytecode::from(vec![0x60, 0x00]))
+            .unwrap();
+        let code_cache = Moka::new(10);
+        let repository = CachedEvmStateRepository::new(repository, EmptyCache(RwLock::new(None)))
+            .with_code_cache(code_cache.clone());
+
+        let actual_code = repository.get_code(&code_hash).unwrap();
+
+        assert_eq!(Some(Bytecode::from(vec![0x60, 0x00])), actual_code);
+        assert_eq!(
+            Some(Bytecode::from(vec![0x60, 0x00])),
+            code_cache.read(&code_hash),
+            "Code not cached"
+        );
+    }
+
+    #[test]
+    fn test_nothing_is_cached_when_repository_fails() {
+        let storage_cache = Moka::new(10);
+        let code_cache = Moka::new(10);
+        let repository = CachedEvmStateRepository::new(FailingEvmRepository, Moka::new(10))
+            .with_storage_cache(storage_cache.clone())
+            .with_code_cache(code_cache.clone());
+
+        assert!(
+            repository.get(&Address::ZERO).is_err(),
+            "Failure not reported"
+        );
+        assert!(
+            repository
+                .get_storage(&Address::ZERO, &U256::one())
+                .is_err(),
+            "Failure not reported"
+        );
+        assert!(
+            repository.get_code(&B256::from(U256::one())).is_err(),
+            "Failure not reported"
+        );
+        assert!(!repository.cache.contains(&Address::ZERO), "Account cached");
+        assert!(
+            !storage_cache.contains(&(Address::ZERO, U256::one())),
+            "Storage slot cached"
+        );
+        assert!(
+            !code_cache.contains(&B256::from(U256::one())),
+            "Code cached"
+        );
+    }
+
+    #[test]
+    fn test_deleted_account_and_its_storage_are_invalidated_in_cache() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace(
+                Address::ZERO,
+                Account::new(4, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+        let cache = Moka::new(10);
+        let storage_cache = Moka::new(10);
+        let mut repository = CachedEvmStateRepository::new(repository, cache.clone())
+            .with_storage_cache(storage_cache.clone());
+        repository.get(&Address::ZERO).unwrap();
+        repository
+            .get_storage(&Address::ZERO, &U256::one())
+            .unwrap();
+
+        repository.delete(&Address::ZERO).unwrap();
+
+        assert!(!cache.contains(&Address::ZERO), "Deleted account cached");
+        assert!(
+            !storage_cache.contains(&(Address::ZERO, U256::one())),
+            "Storage slot of deleted account cached"
+        );
+        assert!(
+            repository.get(&Address::ZERO).unwrap().is_none(),
+            "Deleted account found"
+        );
+    }
+
+    #[test]
+    fn test_invalidated_account_is_reloaded_from_repository() {
+        let expected_account = Account::new(4, U256::zero(), B256::ZERO, B256::ZERO);
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace(Address::ZERO, expected_account.clone())
+            .unwrap();
+        let cache = Moka::new(10);
+        let repository = CachedEvmStateRepository::new(repository, cache.clone());
+        cache.write(
+            Address::ZERO,
+            Account::new(3, U256::zero(), B256::ZERO, B256::ZERO),
+        );
+
+        repository.invalidate(&Address::ZERO);
+
+        let actual_account = repository.get(&Address::ZERO).unwrap();
+
+        assert_eq!(Some(expected_account), actual_account);
+    }
+
+    #[test]
+    fn test_invalidate_all_empties_every_cache() {
+        let cache = Moka::new(10);
+        let storage_cache = Moka::new(10);
+        let code_cache = Moka::new(10);
+        let repository =
+            CachedEvmStateRepository::new(InMemoryEvmStateRepository::default(), cache.clone())
+                .with_storage_cache(storage_cache.clone())
+                .with_code_cache(code_cache.clone());
+        cache.write(
+            Address::ZERO,
+            Account::new(4, U256::zero(), B256::ZERO, B256::ZERO),
+        );
+        storage_cache.write((Address::ZERO, U256::one()), U256::from(7));
+        code_cache.write(B256::from(U256::one()), Bytecode::from(vec![0x60, 0x00]));
+
+        repository.invalidate_all();
+
+        assert!(!cache.contains(&Address::ZERO), "Account cached");
+        assert!(
+            !storage_cache.contains(&(Address::ZERO, U256::one())),
+            "Storage slot cached"
+        );
+        assert!(
+            !code_cache.contains(&B256::from(U256::one())),
+            "Code cached"
+        );
+    }
+
+    #[test]
+    fn test_absence_of_account_is_cached_in_negative_cache() {
+        let negative_cache = Moka::new(10);
+        let repository = CachedEvmStateRepository::new(NoopEvmRepository, Moka::new(10))
+            .with_negative_cache(negative_cache.clone());
+
+        let actual_account = repository.get(&Address::ZERO).unwrap();
+
+        assert!(
+            actual_account.is_none(),
+            "Account found but none was present"
+        );
+        assert!(
+            negative_cache.contains(&Address::ZERO),
+            "Absence not cached"
+        );
+    }
+
+    #[test]
+    fn test_account_created_after_absence_was_cached_is_found() {
+        let expected_account = Account::new(4, U256::zero(), B256::ZERO, B256::ZERO);
+        // Accounts are not cached, so that a stale absence cannot be hidden by the written account
+        let mut repository =
+            CachedEvmStateRepository::new(InMemoryEvmStateRepository::default(), NoopCache)
+                .with_negative_cache(Moka::new(10));
+        repository.get(&Address::ZERO).unwrap();
+
+        repository
+            .replace(Address::ZERO, expected_account.clone())
+            .unwrap();
+
+        let actual_account = repository.get(&Address::ZERO).unwrap();
+
+        assert_eq!(Some(expected_account), actual_account);
+    }
+
+    #[test]
+    fn test_account_loaded_before_concurrent_write_is_not_left_cached() {
+        let inner = Arc::new(GatedEvmRepository::default());
+        ConcurrentEvmStateRepository::replace(
+            inner.as_ref(),
+            Address::ZERO,
+            Account::empty().with_nonce(1),
+        )
+        .unwrap();
+        let repository = Arc::new(
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10))
+                .with_negative_cache(Moka::new(10)),
+        );
+        let (read, written) = inner.gate();
+
+        let load = {
+            let repository = Arc::clone(&repository);
+            thread::spawn(move || repository.get(&Address::ZERO).unwrap())
+        };
+        read.wait();
+        ConcurrentEvmStateRepository::replace(
+            repository.as_ref(),
+            Address::ZERO,
+            Account::empty().with_nonce(2),
+        )
+        .unwrap();
+        written.wait();
+        load.join().unwrap();
+
+        assert_eq!(2, repository.get(&Address::ZERO).unwrap().unwrap().nonce());
+    }
+
+    #[test]
+    fn test_cache_agrees_with_repository_after_concurrent_writes() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let repository = Arc::new(
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10))
+                .with_storage_cache(Moka::new(10)),
+        );
+
+        let handles: Vec<_> = (0..4u64)
+            .map(|i| {
+                let repository = Arc::clone(&repository);
+                thread::spawn(move || {
+                    for j in 0..100 {
+                        ConcurrentEvmStateRepository::replace(
+                            repository.as_ref(),
+                            Address::ZERO,
+                            Account::empty().with_nonce(i * 1000 + j),
+                        )
+                        .unwrap();
+                        ConcurrentEvmStateRepository::replace_storage(
+                            repository.as_ref(),
+                            Address::ZERO,
+                            U256::one(),
+                            U256::from(i * 1000 + j),
+                        )
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(
+            inner.get(&Address::ZERO).unwrap(),
+            repository.get(&Address::ZERO).unwrap()
+        );
+        assert_eq!(
+            inner.get_storage(&Address::ZERO, &U256::one()).unwrap(),
+            repository
+                .get_storage(&Address::ZERO, &U256::one())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_concurrent_misses_of_same_address_load_from_repository_once() {
+        let repository = Arc::new(CachedEvmStateRepository::new(
+            SlowCountingEvmRepository::default(),
+            Moka::new(10),
+        ));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let repository = Arc::clone(&repository);
+                thread::spawn(move || repository.get(&Address::ZERO).unwrap())
+            })
+            .collect();
+
+        for handle in handles {
+            assert!(handle.join().unwrap().is_some(), "Account not found");
+        }
+
+        assert_eq!(1, repository.inner.inner.loads.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_concurrent_misses_of_same_key_all_fail_when_repository_fails() {
+        let repository = Arc::new(
+            CachedEvmStateRepository::new(SlowCountingEvmRepository::failing(), Moka::new(10))
+                .with_storage_cache(Moka::new(10)),
+        );
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let repository = Arc::clone(&repository);
+                thread::spawn(move || {
+                    (
+                        repository.get(&Address::ZERO).map(|_account| ()),
+                        repository
+                            .get_storage(&Address::ZERO, &U256::one())
+                            .map(|_value| ()),
+                    )
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let (account, value) = handle.join().unwrap();
+
+            assert!(
+                matches!(account, Err(Error::Backend(_))),
+                "Failure of account not reported"
+            );
+            assert!(
+                matches!(value, Err(Error::Backend(_))),
+                "Failure of storage slot not reported"
+            );
+            assert_eq!(
+                "backend error: Database is unavailable",
+                account.unwrap_err().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_statistics_report_hits_and_misses_of_account_cache() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace(
+                Address::ZERO,
+                Account::new(4, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
+        let cache = CacheBuilder::new()
+            .with_capacity(10)
+            .with_eviction_policy(EvictionPolicy::LeastRecentlyUsed)
+            .build();
+        let repository = CachedEvmStateRepository::new(repository, cache);
+
+        repository.get(&Address::ZERO).unwrap();
+        repository.get(&Address::ZERO).unwrap();
+        repository.get(&Address::new([1u8; 20])).unwrap();
+
+        let statistics = repository.statistics().accounts;
+
+        assert_eq!(1, statistics.hits);
+        assert_eq!(2, statistics.misses);
+        assert_eq!(1, statistics.inserts);
+        assert_eq!(1, statistics.entry_count);
+        assert_eq!(2, statistics.loads);
+    }
+
+    #[test]
+    fn test_account_written_back_reaches_repository_only_when_flushed() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let mut repository =
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10)).with_write_back(10);
+
+        EvmStateRepository::replace(
+            &mut repository,
+            Address::ZERO,
+            Account::empty().with_nonce(1),
+        )
+        .unwrap();
+
+        assert_eq!(None, inner.get(&Address::ZERO).unwrap());
+        assert_eq!(1, repository.get(&Address::ZERO).unwrap().unwrap().nonce());
+        assert!(repository.is_dirty(), "Deferred write not reported");
+
+        EvmStateRepository::flush(&mut repository).unwrap();
+
+        assert_eq!(1, inner.get(&Address::ZERO).unwrap().unwrap().nonce());
+        assert!(!repository.is_dirty(), "Flushed write still reported");
+    }
+
+    #[test]
+    fn test_accounts_being_flushed_are_read_without_waiting_for_flush() {
+        let inner = Arc::new(GatedEvmRepository::default());
+        let repository = Arc::new(
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10)).with_write_back(10),
+        );
+        ConcurrentEvmStateRepository::replace(
+            repository.as_ref(),
+            Address::ZERO,
+            Account::empty().with_nonce(1),
+        )
+        .unwrap();
+        let (written, released) = inner.gate();
+
+        let flush = {
+            let repository = Arc::clone(&repository);
+            thread::spawn(move || ConcurrentEvmStateRepository::flush(repository.as_ref()))
+        };
+        written.wait();
+        let account = repository.get(&Address::ZERO).unwrap();
+        ConcurrentEvmStateRepository::replace(
+            repository.as_ref(),
+            Address::new([1; 20]),
+            Account::empty(),
+        )
+        .unwrap();
+        released.wait();
+        flush.join().unwrap().unwrap();
+
+        assert_eq!(1, account.unwrap().nonce());
+        assert!(repository.is_dirty(), "Write made during flush not kept");
+    }
+
+    #[test]
+    fn test_failed_flush_before_drop_is_reported_and_keeps_writes() {
+        let mut repository =
+            CachedEvmStateRepository::new(FailingEvmRepository, Moka::new(10)).with_write_back(10);
+        EvmStateRepository::replace(&mut repository, Address::ZERO, Account::empty()).unwrap();
+
+        let result = EvmStateRepository::flush(&mut repository);
+
+        assert!(
+            matches!(result, Err(Error::Backend(_))),
+            "Failed flush not reported"
+        );
+        assert!(repository.is_dirty(), "Writes of failed flush not kept");
+        assert_eq!(
+            Some(Account::empty()),
+            EvmStateRepository::get(&repository, &Address::ZERO).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_dirty_accounts_are_flushed_when_evicted_from_cache() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let cache = CacheBuilder::new().with_capacity(2).build_lru();
+        let mut repository =
+            CachedEvmStateRepository::new(inner.clone(), cache).with_write_back(100);
+
+        for i in 0..2u8 {
+            EvmStateRepository::replace(&mut repository, address(i), account(i as u64)).unwrap();
+        }
+        let dirty_before_eviction = repository.is_dirty();
+        EvmStateRepository::replace(&mut repository, address(2), account(2)).unwrap();
+
+        assert!(dirty_before_eviction, "Accounts flushed before eviction");
+        assert!(!repository.is_dirty(), "Evicted dirty account not flushed");
+        for i in 0..3u8 {
+            assert_eq!(Some(account(i as u64)), inner.get(&address(i)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_dirty_accounts_are_found_after_eviction_from_cache() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let cache = CacheBuilder::new().with_capacity(1).build_lru();
+        let repository = CachedEvmStateRepository::new(inner.clone(), cache).with_write_back(100);
+
+        for i in 0..10u8 {
+            repository
+                .replace(Address::new([i; 20]), Account::empty().with_nonce(i as u64))
+                .unwrap();
+        }
+
+        for i in 0..10u8 {
+            let account = repository.get(&Address::new([i; 20])).unwrap().unwrap();
+            assert_eq!(i as u64, account.nonce());
+        }
+        assert_eq!(None, inner.get(&Address::new([0u8; 20])).unwrap());
+    }
+
+    #[test]
+    fn test_dirty_entries_are_flushed_when_write_back_is_full() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let repository =
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10)).with_write_back(2);
+
+        for i in 0..3u8 {
+            repository
+                .replace(Address::new([i; 20]), Account::empty())
+                .unwrap();
+        }
+
+        assert!(
+            inner.get(&Address::new([0u8; 20])).unwrap().is_some(),
+            "First account not flushed"
+        );
+        assert!(
+            inner.get(&Address::new([1u8; 20])).unwrap().is_some(),
+            "Second account not flushed"
+        );
+        assert!(
+            inner.get(&Address::new([2u8; 20])).unwrap().is_none(),
+            "Third account flushed"
+        );
+    }
+
+    #[test]
+    fn test_dirty_entries_are_flushed_on_drop() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let repository = CachedEvmStateRepository::new(inner.clone(), Moka::new(10))
+            .with_storage_cache(Moka::new(10))
+            .with_write_back(10);
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+
+        drop(repository);
+
+        assert_eq!(
+            U256::from(7),
+            inner.get_storage(&Address::ZERO, &U256::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_changeset_holds_writes_deferred_in_write_back_mode() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        ConcurrentEvmStateRepository::replace(inner.as_ref(), Address::ZERO, Account::empty())
+            .unwrap();
+        let repository =
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10)).with_write_back(10);
+        let changed_account = Account::empty().with_nonce(1);
+
+        repository
+            .replace(Address::ZERO, changed_account.clone())
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+        let changeset = repository.changeset().unwrap();
+        repository.flush().unwrap();
+
+        assert_eq!(2, changeset.len());
+        assert_eq!(
+            U256::from(7),
+            changeset.storage[&Address::ZERO][&U256::one()].after
+        );
+        assert_eq!(
+            Some(Account::empty()),
+            changeset.accounts[&Address::ZERO].before
+        );
+        assert_eq!(
+            Some(changed_account),
+            changeset.accounts[&Address::ZERO].after
+        );
+        assert!(
+            repository.changeset().unwrap().is_empty(),
+            "Flushed changes are kept"
+        );
+    }
+
+    #[test]
+    fn test_all_storage_includes_writes_deferred_in_write_back_mode() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        for (slot, value) in [(1, 7), (2, 8)] {
+            ConcurrentEvmStateRepository::replace_storage(
+                inner.as_ref(),
+                Address::ZERO,
+                U256::from(slot),
+                U256::from(value),
+            )
+            .unwrap();
+        }
+        let repository =
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10)).with_write_back(10);
+
+        repository
+            .replace_storage(Address::ZERO, U256::from(2), U256::zero())
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::from(3), U256::from(9))
+            .unwrap();
+        let mut actual_storage = repository.get_all_storage(&Address::ZERO).unwrap();
+        actual_storage.sort();
+
+        assert_eq!(
+            vec![(U256::one(), U256::from(7)), (U256::from(3), U256::from(9))],
+            actual_storage
+        );
+    }
+
+    #[test]
+    fn test_changeset_is_empty_in_write_through_mode() {
+        let repository = CachedEvmStateRepository::new(
+            Arc::new(ConcurrentInMemoryEvmStateRepository::default()),
+            Moka::new(10),
+        );
+
+        repository.replace(Address::ZERO, Account::empty()).unwrap();
+
+        assert!(
+            repository.changeset().unwrap().is_empty(),
+            "Written through changes are kept"
+        );
+    }
+
+    #[test]
+    fn test_storage_of_account_deleted_in_write_back_mode_reads_as_zero() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        ConcurrentEvmStateRepository::replace_storage(
+            inner.as_ref(),
+            Address::ZERO,
+            U256::one(),
+            U256::from(7),
+        )
+        .unwrap();
+        let repository =
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10)).with_write_back(10);
+
+        repository.delete(&Address::ZERO).unwrap();
+
+        assert_eq!(
+            U256::zero(),
+            repository
+                .get_storage(&Address::ZERO, &U256::one())
+                .unwrap()
+        );
+
+        repository.flush().unwrap();
+
+        assert_eq!(
+            U256::zero(),
+            inner.get_storage(&Address::ZERO, &U256::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_accounts_written_back_from_multiple_threads_are_flushed() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let repository = Arc::new(
+            CachedEvmStateRepository::new(inner.clone(), Moka::new(10)).with_write_back(100),
+        );
+
+        let handles: Vec<_> = (0..4u8)
+            .map(|i| {
+                let repository = Arc::clone(&repository);
+                thread::spawn(move || {
+                    ConcurrentEvmStateRepository::replace(
+                        repository.as_ref(),
+                        Address::new([i; 20]),
+                        Account::empty(),
+                    )
+                    .unwrap()
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        ConcurrentEvmStateRepository::flush(repository.as_ref()).unwrap();
+
+        for i in 0..4u8 {
+            assert!(
+                inner.get(&Address::new([i; 20])).unwrap().is_some(),
+                "Account not flushed"
+            );
+        }
+    }
+
+    #[test]
+    fn test_accounts_read_in_batch_are_loaded_from_repository_only_when_cache_misses() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let cache = Moka::new(10);
+        let repository = CachedEvmStateRepository::new(inner.clone(), cache.clone());
+        cache.write(Address::new([1u8; 20]), Account::empty().with_nonce(1));
+        ConcurrentEvmStateRepository::replace(
+            inner.as_ref(),
+            Address::new([1u8; 20]),
+            Account::empty().with_nonce(10),
+        )
+        .unwrap();
+        ConcurrentEvmStateRepository::replace(
+            inner.as_ref(),
+            Address::new([2u8; 20]),
+            Account::empty().with_nonce(2),
+        )
+        .unwrap();
+
+        let actual_accounts = repository
+            .get_many(&[
+                Address::new([1u8; 20]),
+                Address::new([2u8; 20]),
+                Address::new([3u8; 20]),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                Some(Account::empty().with_nonce(1)),
+                Some(Account::empty().with_nonce(2)),
+                None
+            ],
+            actual_accounts
+        );
+        assert!(
+            cache.contains(&Address::new([2u8; 20])),
+            "Loaded account not cached"
+        );
+    }
+
+    #[test]
+    fn test_absence_of_accounts_read_in_batch_is_cached_in_negative_cache() {
+        let negative_cache = Moka::new(10);
+        let repository = CachedEvmStateRepository::new(NoopEvmRepository, Moka::new(10))
+            .with_negative_cache(negative_cache.clone());
+
+        let actual_accounts = repository
+            .get_many(&[Address::ZERO, Address::ZERO])
+            .unwrap();
+
+        assert_eq!(vec![None, None], actual_accounts);
+        assert!(
+            negative_cache.contains(&Address::ZERO),
+            "Absence not cached"
+        );
+    }
+
+    #[test]
+    fn test_accounts_replaced_in_batch_are_cached_and_written_to_repository() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let cache = Moka::new(10);
+        let mut repository = CachedEvmStateRepository::new(inner.clone(), cache.clone())
+            .with_negative_cache(Moka::new(10));
+        repository.get(&Address::ZERO).unwrap();
+
+        EvmStateRepository::replace_many(
+            &mut repository,
+            vec![
+                (Address::ZERO, Account::empty().with_nonce(1)),
+                (Address::new([1u8; 20]), Account::empty().with_nonce(2)),
+            ],
+        )
+        .unwrap();
+
+        assert!(cache.contains(&Address::ZERO), "Account not cached");
+        assert_eq!(1, repository.get(&Address::ZERO).unwrap().unwrap().nonce());
+        assert_eq!(
+            2,
+            inner
+                .get(&Address::new([1u8; 20]))
+                .unwrap()
+                .unwrap()
+                .nonce()
+        );
+    }
+
+    #[test]
+    fn test_accounts_read_in_batch_include_dirty_ones_in_write_back_mode() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let cache = CacheBuilder::new().with_capacity(1).build_lru();
+        let repository = CachedEvmStateRepository::new(inner.clone(), cache).with_write_back(10);
+        ConcurrentEvmStateRepository::replace(
+            inner.as_ref(),
+            Address::ZERO,
+            Account::empty().with_nonce(1),
+        )
+        .unwrap();
+
+        ConcurrentEvmStateRepository::replace_many(
+            &repository,
+            vec![
+                (Address::new([1u8; 20]), Account::empty().with_nonce(2)),
+                (Address::new([2u8; 20]), Account::empty().with_nonce(3)),
+            ],
+        )
+        .unwrap();
+        let actual_accounts = repository
+            .get_many(&[
+                Address::ZERO,
+                Address::new([1u8; 20]),
+                Address::new([2u8; 20]),
+            ])
+            .unwrap();
+
+        let actual_nonces: Vec<_> = actual_accounts
+            .iter()
+            .map(|account| account.as_ref().unwrap().nonce())
+            .collect();
+        assert_eq!(vec![1, 2, 3], actual_nonces);
+        assert_eq!(None, inner.get(&Address::new([1u8; 20])).unwrap());
+    }
 }
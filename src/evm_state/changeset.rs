@@ -0,0 +1,299 @@
+//! Changes of accounts and their storage between two states, e.g. made by executing a block.
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{
+//!     Account, Address, EvmStateRepository, InMemoryEvmStateRepository,
+//!     JournaledEvmStateRepository,
+//! };
+//!
+//! let mut repository = JournaledEvmStateRepository::new(InMemoryEvmStateRepository::default());
+//! repository.replace(Address::ZERO, Account::empty().with_nonce(1)).unwrap();
+//!
+//! let changeset = repository.changeset().unwrap();
+//! repository.commit().unwrap();
+//! let mut inner = repository.into_inner();
+//!
+//! changeset.inverse().apply_to(&mut inner).unwrap();
+//! assert_eq!(None, inner.get(&Address::ZERO).unwrap());
+//! ```
+use crate::error::Result;
+use crate::evm_state::{Account, Address, EvmStateRepository};
+use primitive_types::U256;
+use std::collections::BTreeMap;
+
+/// A change of an account, where [`None`] stands for a non-existent account.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountChange {
+    pub before: Option<Account>,
+    pub after: Option<Account>,
+}
+
+/// A change of a storage slot, where zero stands for an unset slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StorageChange {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::quantity")
+    )]
+    pub before: U256,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::quantity")
+    )]
+    pub after: U256,
+}
+
+/// Accounts and storage slots that changed between two states, each with its value before and
+/// after the change.
+///
+/// Contract code is not part of a changeset, as it is addressed by its hash and never changes.
+/// Deleting an account wipes its storage, so every slot it held is recorded as changed to zero,
+/// and the inverse changeset restores them. An account removed by the change is deleted together
+/// with its storage when the changeset is applied.
+///
+/// Entries are ordered by addresses, so that the same changes always produce the same changeset.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Changeset {
+    /// Changes of accounts by their addresses.
+    pub accounts: BTreeMap<Address, AccountChange>,
+    /// Changes of storage slots by addresses of their accounts and by the slots.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::evm_state::serialization::storage_changes")
+    )]
+    pub storage: BTreeMap<Address, BTreeMap<U256, StorageChange>>,
+}
+
+impl Changeset {
+    /// Records a change of an account associated with the `address` from `before` to `after`.
+    ///
+    /// A change of an account that has already changed is merged with the previous one, keeping
+    /// its value before both changes. A change that results in the original value is dropped.
+    pub fn insert(&mut self, address: Address, before: Option<Account>, after: Option<Account>) {
+        let before = match self.accounts.remove(&address) {
+            Some(change) => change.before,
+            None => before,
+        };
+        if before != after {
+            self.accounts
+                .insert(address, AccountChange { before, after });
+        }
+    }
+
+    /// Records a change of the storage `slot` of an account associated with the `address` from
+    /// `before` to `after`, merging it with a previous change of the slot the same way as
+    /// [`Changeset::insert`] does.
+    pub fn insert_storage(&mut self, address: Address, slot: U256, before: U256, after: U256) {
+        let slots = self.storage.entry(address).or_default();
+        let before = match slots.remove(&slot) {
+            Some(change) => change.before,
+            None => before,
+        };
+        if before != after {
+            slots.insert(slot, StorageChange { before, after });
+        }
+        if slots.is_empty() {
+            self.storage.remove(&address);
+        }
+    }
+
+    /// Merges `changes` made after the changes of this changeset, e.g. to combine changesets of
+    /// consecutive transactions into a changeset of their block.
+    pub fn extend(&mut self, changes: Changeset) {
+        for (address, change) in changes.accounts {
+            self.insert(address, change.before, change.after);
+        }
+        for (address, slots) in changes.storage {
+            for (slot, change) in slots {
+                self.insert_storage(address, slot, change.before, change.after);
+            }
+        }
+    }
+
+    /// Returns the changeset that undoes this one, e.g. to roll back a block.
+    pub fn inverse(self) -> Self {
+        Self {
+            accounts: self
+                .accounts
+                .into_iter()
+                .map(|(address, change)| {
+                    let inverse = AccountChange {
+                        before: change.after,
+                        after: change.before,
+                    };
+                    (address, inverse)
+                })
+                .collect(),
+            storage: self
+                .storage
+                .into_iter()
+                .map(|(address, slots)| {
+                    let inverse = slots
+                        .into_iter()
+                        .map(|(slot, change)| {
+                            let inverse = StorageChange {
+                                before: change.after,
+                                after: change.before,
+                            };
+                            (slot, inverse)
+                        })
+                        .collect();
+                    (address, inverse)
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the values of accounts and storage slots after the change into the `repository`,
+    /// deleting accounts that do not exist after it before their storage is written.
+    ///
+    /// Values before the change are not checked against the `repository`.
+    pub fn apply_to(&self, repository: &mut impl EvmStateRepository) -> Result<()> {
+        let mut accounts = Vec::with_capacity(self.accounts.len());
+
+        for (address, change) in &self.accounts {
+            match &change.after {
+                Some(account) => accounts.push((*address, account.clone())),
+                None => repository.delete(address)?,
+            }
+        }
+        repository.replace_many(accounts)?;
+
+        for (address, slots) in &self.storage {
+            let deleted = matches!(
+                self.accounts.get(address),
+                Some(AccountChange { after: None, .. })
+            );
+            for (slot, change) in slots {
+                // Storage of a deleted account has been wiped together with it
+                if !deleted || !change.after.is_zero() {
+                    repository.replace_storage(*address, *slot, change.after)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of changed accounts and storage slots.
+    pub fn len(&self) -> usize {
+        self.accounts.len() + self.storage.values().map(BTreeMap::len).sum::<usize>()
+    }
+
+    /// Checks whether no account or storage slot changed.
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.storage.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::test_support::{account, address};
+    use crate::evm_state::InMemoryEvmStateRepository;
+
+    #[test]
+    fn test_consecutive_changes_of_account_are_merged() {
+        let mut changeset = Changeset::default();
+
+        changeset.insert(address(1), None, Some(account(1)));
+        changeset.insert(address(1), Some(account(1)), Some(account(2)));
+
+        assert_eq!(
+            Some(&AccountChange {
+                before: None,
+                after: Some(account(2))
+            }),
+            changeset.accounts.get(&address(1))
+        );
+    }
+
+    #[test]
+    fn test_change_back_to_original_value_is_dropped() {
+        let mut changeset = Changeset::default();
+
+        changeset.insert(address(1), Some(account(1)), Some(account(2)));
+        changeset.insert(address(1), Some(account(2)), Some(account(1)));
+
+        assert!(changeset.is_empty(), "Reverted change is kept");
+    }
+
+    #[test]
+    fn test_extended_changeset_merges_later_changes() {
+        let mut changeset = Changeset::default();
+        changeset.insert(address(1), None, Some(account(1)));
+        let mut later_changeset = Changeset::default();
+        later_changeset.insert(address(1), Some(account(1)), Some(account(2)));
+        later_changeset.insert(address(2), Some(account(2)), None);
+
+        changeset.extend(later_changeset);
+
+        assert_eq!(2, changeset.len());
+        assert_eq!(None, changeset.accounts[&address(1)].before);
+        assert_eq!(Some(account(2)), changeset.accounts[&address(1)].after);
+        assert_eq!(None, changeset.accounts[&address(2)].after);
+    }
+
+    #[test]
+    fn test_applied_changeset_writes_accounts_after_change() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository.replace(address(2), account(2)).unwrap();
+        let mut changeset = Changeset::default();
+        changeset.insert(address(1), None, Some(account(1)));
+        changeset.insert(address(2), Some(account(2)), None);
+
+        changeset.apply_to(&mut repository).unwrap();
+
+        assert_eq!(Some(account(1)), repository.get(&address(1)).unwrap());
+        assert_eq!(None, repository.get(&address(2)).unwrap());
+    }
+
+    #[test]
+    fn test_applied_inverse_changeset_rolls_back_changes() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository.replace(address(2), account(2)).unwrap();
+        let expected_repository = repository.clone();
+        let mut changeset = Changeset::default();
+        changeset.insert(address(1), None, Some(account(1)));
+        changeset.insert(address(2), Some(account(2)), Some(account(3)));
+        changeset.apply_to(&mut repository).unwrap();
+
+        changeset.inverse().apply_to(&mut repository).unwrap();
+
+        assert_eq!(expected_repository, repository);
+    }
+
+    #[test]
+    fn test_applied_inverse_changeset_rolls_back_storage() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository.replace(address(2), account(2)).unwrap();
+        repository
+            .replace_storage(address(2), U256::one(), U256::from(7))
+            .unwrap();
+        let expected_repository = repository.clone();
+        let mut changeset = Changeset::default();
+        changeset.insert(address(1), None, Some(account(1)));
+        changeset.insert_storage(address(1), U256::one(), U256::zero(), U256::from(5));
+        changeset.insert(address(2), Some(account(2)), None);
+        changeset.insert_storage(address(2), U256::one(), U256::from(7), U256::zero());
+        changeset.apply_to(&mut repository).unwrap();
+
+        changeset.inverse().apply_to(&mut repository).unwrap();
+
+        assert_eq!(expected_repository, repository);
+    }
+
+    #[test]
+    fn test_storage_change_back_to_original_value_is_dropped() {
+        let mut changeset = Changeset::default();
+
+        changeset.insert_storage(address(1), U256::one(), U256::zero(), U256::from(5));
+        changeset.insert_storage(address(1), U256::one(), U256::from(5), U256::zero());
+
+        assert!(changeset.is_empty(), "Reverted storage change is kept");
+    }
+}
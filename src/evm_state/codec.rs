@@ -0,0 +1,233 @@
+//! [RLP] encoding and decoding of [`Account`] as used by the state trie and snapshots.
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{Account, SlimAccount};
+//!
+//! let account = Account::empty().with_nonce(1);
+//!
+//! let bytes = rlp::encode(&account);
+//! let slim_bytes = rlp::encode(&SlimAccount(account.clone()));
+//!
+//! assert_eq!(account, rlp::decode::<Account>(&bytes).unwrap());
+//! assert_eq!(account, rlp::decode::<SlimAccount>(&slim_bytes).unwrap().0);
+//! assert!(slim_bytes.len() < bytes.len());
+//! ```
+//!
+//! [RLP]: https://ethereum.org/en/developers/docs/data-structures-and-encoding/rlp/
+use crate::evm_state::{Account, B256, EMPTY_CODE_HASH, EMPTY_ROOT_HASH};
+use primitive_types::U256;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+/// An [`Account`] encoded in the slim format of state snapshots, which replaces the empty storage
+/// root and the empty code hash by empty strings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlimAccount(pub Account);
+
+/// Returns big-endian bytes of `value` without leading zeros, which is how RLP encodes integers.
+pub(crate) fn trimmed_bytes(value: &U256) -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let leading_zeros = value.leading_zeros() as usize / 8;
+    bytes[leading_zeros..].to_vec()
+}
+
+/// Decodes a [`U256`], rejecting encodings longer than 32 bytes or with leading zeros.
+pub(crate) fn decode_u256(rlp: &Rlp<'_>) -> Result<U256, DecoderError> {
+    rlp.decoder().decode_value(|bytes| match bytes {
+        [0, ..] => Err(DecoderError::RlpInvalidIndirection),
+        bytes if bytes.len() > 32 => Err(DecoderError::RlpIsTooBig),
+        bytes => Ok(U256::from_big_endian(bytes)),
+    })
+}
+
+fn decode_b256(rlp: &Rlp<'_>) -> Result<B256, DecoderError> {
+    rlp.decoder().decode_value(|bytes| {
+        <[u8; 32]>::try_from(bytes)
+            .map(B256::new)
+            .map_err(|_| DecoderError::RlpInvalidLength)
+    })
+}
+
+/// Decodes a hash of the slim format, where an empty string stands for the `empty` hash.
+fn decode_slim_b256(rlp: &Rlp<'_>, empty: B256) -> Result<B256, DecoderError> {
+    match rlp.data()? {
+        [] => Ok(empty),
+        _ => decode_b256(rlp),
+    }
+}
+
+fn expect_list_of_four(rlp: &Rlp<'_>) -> Result<(), DecoderError> {
+    if !rlp.is_list() {
+        return Err(DecoderError::RlpExpectedToBeList);
+    }
+    if rlp.item_count()? != 4 {
+        return Err(DecoderError::RlpIncorrectListLen);
+    }
+
+    Ok(())
+}
+
+/// Encodes the account as a list of `[nonce, balance, storageRoot, codeHash]`.
+impl Encodable for Account {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        stream
+            .begin_list(4)
+            .append(&self.nonce)
+            .append(&trimmed_bytes(&self.balance).as_slice())
+            .append(&self.storage_root.as_bytes().as_slice())
+            .append(&self.code_hash.as_bytes().as_slice());
+    }
+}
+
+/// Decodes the account from a list of `[nonce, balance, storageRoot, codeHash]`, rejecting
+/// integers with leading zeros and hashes of other than 32 bytes.
+impl Decodable for Account {
+    fn decode(rlp: &Rlp<'_>) -> Result<Self, DecoderError> {
+        expect_list_of_four(rlp)?;
+
+        Ok(Self {
+            nonce: rlp.val_at(0)?,
+            balance: decode_u256(&rlp.at(1)?)?,
+            storage_root: decode_b256(&rlp.at(2)?)?,
+            code_hash: decode_b256(&rlp.at(3)?)?,
+        })
+    }
+}
+
+impl Encodable for SlimAccount {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        let account = &self.0;
+        stream
+            .begin_list(4)
+            .append(&account.nonce)
+            .append(&trimmed_bytes(&account.balance).as_slice());
+
+        for (hash, empty) in [
+            (&account.storage_root, EMPTY_ROOT_HASH),
+            (&account.code_hash, EMPTY_CODE_HASH),
+        ] {
+            if *hash == empty {
+                stream.append_empty_data();
+            } else {
+                stream.append(&hash.as_bytes().as_slice());
+            }
+        }
+    }
+}
+
+impl Decodable for SlimAccount {
+    fn decode(rlp: &Rlp<'_>) -> Result<Self, DecoderError> {
+        expect_list_of_four(rlp)?;
+
+        Ok(Self(Account {
+            nonce: rlp.val_at(0)?,
+            balance: decode_u256(&rlp.at(1)?)?,
+            storage_root: decode_slim_b256(&rlp.at(2)?, EMPTY_ROOT_HASH)?,
+            code_hash: decode_slim_b256(&rlp.at(3)?, EMPTY_CODE_HASH)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn any_account() -> impl Strategy<Value = Account> {
+        (
+            any::<u64>(),
+            any::<[u64; 4]>(),
+            any::<[u8; 32]>(),
+            any::<[u8; 32]>(),
+        )
+            .prop_map(|(nonce, balance, code_hash, storage_root)| {
+                Account::new(
+                    nonce,
+                    U256(balance),
+                    B256::new(code_hash),
+                    B256::new(storage_root),
+                )
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_account_encoded_and_decoded_is_unchanged(account in any_account()) {
+            let bytes = rlp::encode(&account);
+
+            prop_assert_eq!(account, rlp::decode::<Account>(&bytes).unwrap());
+        }
+
+        #[test]
+        fn test_slim_account_encoded_and_decoded_is_unchanged(account in any_account()) {
+            let bytes = rlp::encode(&SlimAccount(account.clone()));
+
+            prop_assert_eq!(account, rlp::decode::<SlimAccount>(&bytes).unwrap().0);
+        }
+    }
+
+    #[test]
+    fn test_empty_account_is_encoded_as_canonical_vector() {
+        let actual_bytes = rlp::encode(&Account::empty());
+
+        assert_eq!(
+            "f8448080a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421a0c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            hex::encode(actual_bytes)
+        );
+    }
+
+    #[test]
+    fn test_account_with_nonce_and_balance_is_encoded_as_canonical_vector() {
+        let account = Account::empty().with_nonce(1).with_balance(U256::exp10(18));
+
+        let actual_bytes = rlp::encode(&account);
+
+        assert_eq!(
+            "f84c01880de0b6b3a7640000a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421a0c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            hex::encode(actual_bytes)
+        );
+    }
+
+    #[test]
+    fn test_empty_slim_account_is_encoded_as_canonical_vector() {
+        let actual_bytes = rlp::encode(&SlimAccount(Account::empty()));
+
+        assert_eq!("c480808080", hex::encode(actual_bytes));
+    }
+
+    #[test]
+    fn test_account_with_leading_zero_in_balance_is_not_decoded() {
+        let mut stream = RlpStream::new_list(4);
+        stream
+            .append(&0u64)
+            .append(&[0u8, 1].as_slice())
+            .append(&EMPTY_ROOT_HASH.as_bytes().as_slice())
+            .append(&EMPTY_CODE_HASH.as_bytes().as_slice());
+
+        let actual_account = rlp::decode::<Account>(&stream.out());
+
+        assert_eq!(Err(DecoderError::RlpInvalidIndirection), actual_account);
+    }
+
+    #[test]
+    fn test_account_with_short_hash_is_not_decoded() {
+        let mut stream = RlpStream::new_list(4);
+        stream
+            .append(&0u64)
+            .append_empty_data()
+            .append(&[1u8; 31].as_slice())
+            .append(&EMPTY_CODE_HASH.as_bytes().as_slice());
+
+        let actual_account = rlp::decode::<Account>(&stream.out());
+
+        assert_eq!(Err(DecoderError::RlpInvalidLength), actual_account);
+    }
+
+    #[test]
+    fn test_list_of_other_than_four_items_is_not_decoded() {
+        let actual_account = rlp::decode::<Account>(&hex::decode("c3808080").unwrap());
+
+        assert_eq!(Err(DecoderError::RlpIncorrectListLen), actual_account);
+    }
+}
@@ -1,41 +1,221 @@
 /// Concurrent, in-memory implementation of [`EvmStateRepository`].
 ///
 /// All data is kept in-memory and can be accessed from a multiple threads concurrently.
-use crate::evm_state::{Account, Address, EvmStateRepository};
+use crate::error::Result;
+use crate::evm_state::{
+    check_code_hash, trie, Account, AccountProof, Address, Bytecode, ConcurrentEvmStateRepository,
+    EvmStateRepository, EvmStateSnapshot, B256,
+};
 use dashmap::DashMap;
+use primitive_types::U256;
+use std::collections::HashMap;
 
 /// In-memory concurrent multithreaded ideal for benchmarking.
 #[derive(Debug, Clone, Default)]
 pub struct ConcurrentInMemoryEvmStateRepository {
     accounts: DashMap<Address, Account>,
+    storage: DashMap<Address, HashMap<U256, U256>>,
+    codes: DashMap<B256, Bytecode>,
+}
+
+impl ConcurrentInMemoryEvmStateRepository {
+    /// Creates a repository holding the whole state of the `snapshot`.
+    pub fn load(snapshot: EvmStateSnapshot) -> Self {
+        Self {
+            accounts: snapshot.accounts.into_iter().collect(),
+            storage: snapshot
+                .storage
+                .into_iter()
+                .map(|(address, slots)| (address, slots.into_iter().collect()))
+                .collect(),
+            codes: snapshot.codes.into_iter().collect(),
+        }
+    }
+
+    /// Takes a snapshot of the whole state held by the repository.
+    ///
+    /// Writes made concurrently with the dump may or may not be included in the snapshot.
+    pub fn dump(&self) -> EvmStateSnapshot {
+        EvmStateSnapshot {
+            accounts: self
+                .accounts
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().clone()))
+                .collect(),
+            storage: self
+                .storage
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().clone().into_iter().collect()))
+                .collect(),
+            codes: self
+                .codes
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().clone()))
+                .collect(),
+        }
+    }
+
+    /// Computes the state root of the whole state held by the repository.
+    ///
+    /// Storage roots of accounts are computed from their contract storage, regardless of the
+    /// storage roots held by the accounts. Writes made concurrently with the computation may or may
+    /// not be included in the state root.
+    pub fn state_root(&self) -> B256 {
+        trie::state_root(self.accounts_with_storage_roots())
+    }
+
+    /// Creates the proof of an account with the `address` and the requested `slots` of its
+    /// contract storage against the [state root](Self::state_root) of the repository.
+    ///
+    /// Writes made concurrently with the computation may or may not be included in the proof.
+    pub fn proof(&self, address: &Address, slots: &[U256]) -> AccountProof {
+        let storage = self.storage.get(address).map(|storage| storage.clone());
+        let account = self.accounts.get(address).map(|account| {
+            let storage_root = trie::storage_root(storage.iter().flatten());
+            account.clone().with_storage_root(storage_root)
+        });
+
+        AccountProof::new(
+            address,
+            account,
+            self.accounts_with_storage_roots(),
+            storage.as_ref(),
+            slots,
+        )
+    }
+
+    fn accounts_with_storage_roots(&self) -> impl Iterator<Item = (Address, Account)> + '_ {
+        self.accounts.iter().map(|entry| {
+            let storage_root = match self.storage.get(entry.key()) {
+                Some(storage) => trie::storage_root(storage.iter()),
+                None => trie::storage_root([]),
+            };
+            (
+                *entry.key(),
+                entry.value().clone().with_storage_root(storage_root),
+            )
+        })
+    }
 }
 
 impl EvmStateRepository for ConcurrentInMemoryEvmStateRepository {
-    fn get(&self, address: &Address) -> Option<Account> {
-        self.accounts.get(address).map(|v| v.clone())
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        Ok(self.accounts.get(address).map(|v| v.clone()))
+    }
+
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        Ok(addresses
+            .iter()
+            .map(|address| self.accounts.get(address).map(|v| v.clone()))
+            .collect())
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        ConcurrentEvmStateRepository::replace(self, address, account)
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        self.accounts.extend(accounts);
+
+        Ok(())
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        ConcurrentEvmStateRepository::delete(self, address)
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        Ok(self
+            .storage
+            .get(address)
+            .and_then(|storage| storage.get(slot).copied())
+            .unwrap_or_default())
+    }
+
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        Ok(self
+            .storage
+            .get(address)
+            .map(|storage| {
+                storage
+                    .iter()
+                    .filter(|(_slot, value)| !value.is_zero())
+                    .map(|(slot, value)| (*slot, *value))
+                    .collect()
+            })
+            .unwrap_or_default())
     }
 
-    fn replace(&mut self, address: Address, account: Account) {
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_storage(self, address, slot, value)
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        Ok(self.codes.get(code_hash).map(|v| v.clone()))
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_code(self, code_hash, code)
+    }
+}
+
+impl ConcurrentEvmStateRepository for ConcurrentInMemoryEvmStateRepository {
+    fn replace(&self, address: Address, account: Account) -> Result<()> {
         self.accounts.insert(address, account);
+
+        Ok(())
+    }
+
+    fn replace_many(&self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        for (address, account) in accounts {
+            self.accounts.insert(address, account);
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, address: &Address) -> Result<()> {
+        self.accounts.remove(address);
+        self.storage.remove(address);
+
+        Ok(())
+    }
+
+    fn replace_storage(&self, address: Address, slot: U256, value: U256) -> Result<()> {
+        self.storage.entry(address).or_default().insert(slot, value);
+
+        Ok(())
+    }
+
+    fn replace_code(&self, code_hash: B256, code: Bytecode) -> Result<()> {
+        check_code_hash(&code_hash, &code)?;
+        self.codes.insert(code_hash, code);
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use primitive_types::{H160, U256};
+    use crate::evm_state::primitives::keccak256;
+    use primitive_types::H160;
+    use std::sync::Arc;
+    use std::thread;
 
     #[test]
     fn test_account_by_existent_address_from_repository_is_found() {
-        let mut repository = ConcurrentInMemoryEvmStateRepository::default();
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
 
-        repository.replace(
-            Address::from(H160::zero()),
-            Account::new(0, U256::zero(), U256::zero(), U256::zero()),
-        );
+        repository
+            .replace(
+                Address::from(H160::zero()),
+                Account::new(0, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
 
-        let actual_account = repository.get(&Address::from(H160::zero()));
-        let expected_account = Account::new(0, U256::zero(), U256::zero(), U256::zero());
+        let actual_account = repository.get(&Address::from(H160::zero())).unwrap();
+        let expected_account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
 
         assert!(actual_account.is_some(), "Account not found");
 
@@ -48,11 +228,164 @@ mod tests {
     fn test_account_by_non_existent_address_from_repository_is_not_found() {
         let repository = ConcurrentInMemoryEvmStateRepository::default();
 
-        let actual_account = repository.get(&Address::from(H160::zero()));
+        let actual_account = repository.get(&Address::from(H160::zero())).unwrap();
 
         assert!(
             actual_account.is_none(),
             "Account found but none was present"
         );
     }
+
+    #[test]
+    fn test_storage_slot_written_to_repository_is_read_back() {
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+
+        repository
+            .replace_storage(Address::from(H160::zero()), U256::one(), U256::from(7))
+            .unwrap();
+
+        let actual_value = repository
+            .get_storage(&Address::from(H160::zero()), &U256::one())
+            .unwrap();
+
+        assert_eq!(U256::from(7), actual_value);
+    }
+
+    #[test]
+    fn test_storage_slot_never_written_to_repository_reads_as_zero() {
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+
+        let actual_value = repository
+            .get_storage(&Address::from(H160::zero()), &U256::one())
+            .unwrap();
+
+        assert_eq!(U256::zero(), actual_value);
+    }
+
+    #[test]
+    fn test_code_by_existent_hash_from_repository_is_found() {
+        let code_hash = B256::new(keccak256([0x60, 0x00]));
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+
+        repository
+            .replace_code(code_hash, Bytecode::from(vec![0x60, 0x00]))
+            .unwrap();
+
+        let actual_code = repository.get_code(&code_hash).unwrap();
+
+        assert_eq!(Some(Bytecode::from(vec![0x60, 0x00])), actual_code);
+    }
+
+    #[test]
+    fn test_deleted_account_is_not_found_and_its_storage_is_cleared() {
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+        repository
+            .replace(
+                Address::from(H160::zero()),
+                Account::new(0, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
+        repository
+            .replace_storage(Address::from(H160::zero()), U256::one(), U256::from(7))
+            .unwrap();
+
+        repository.delete(&Address::from(H160::zero())).unwrap();
+
+        let actual_account = repository.get(&Address::from(H160::zero())).unwrap();
+        let actual_value = repository
+            .get_storage(&Address::from(H160::zero()), &U256::one())
+            .unwrap();
+
+        assert!(actual_account.is_none(), "Deleted account found");
+        assert_eq!(U256::zero(), actual_value);
+    }
+
+    #[test]
+    fn test_accounts_written_from_multiple_threads_behind_arc_are_found() {
+        let repository = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+
+        let handles: Vec<_> = (0..4u8)
+            .map(|i| {
+                let repository = Arc::clone(&repository);
+                thread::spawn(move || {
+                    repository
+                        .replace(
+                            Address::new([i; 20]),
+                            Account::new(i as u64, U256::zero(), B256::ZERO, B256::ZERO),
+                        )
+                        .unwrap();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for i in 0..4u8 {
+            let actual_account = repository.get(&Address::new([i; 20])).unwrap();
+            let expected_account = Account::new(i as u64, U256::zero(), B256::ZERO, B256::ZERO);
+
+            assert_eq!(Some(expected_account), actual_account);
+        }
+    }
+
+    #[test]
+    fn test_state_dumped_from_repository_is_loaded_back() {
+        let code_hash = B256::new(keccak256([0x60, 0x00]));
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+        repository
+            .replace(
+                Address::ZERO,
+                Account::new(1, U256::from(2), code_hash, B256::ZERO),
+            )
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+        repository
+            .replace_code(code_hash, Bytecode::from(vec![0x60, 0x00]))
+            .unwrap();
+        let snapshot = repository.dump();
+
+        let actual_snapshot = ConcurrentInMemoryEvmStateRepository::load(snapshot.clone()).dump();
+
+        assert_eq!(snapshot, actual_snapshot);
+        assert_eq!(1, actual_snapshot.accounts.len());
+        assert_eq!(
+            Some(&U256::from(7)),
+            actual_snapshot.storage[&Address::ZERO].get(&U256::one())
+        );
+    }
+
+    #[test]
+    fn test_state_root_of_repository_matches_state_root_of_its_dump() {
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+        repository
+            .replace(Address::ZERO, Account::empty().with_balance(U256::from(2)))
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+
+        let actual_root = repository.state_root();
+
+        assert_eq!(repository.dump().state_root(), actual_root);
+    }
+
+    #[test]
+    fn test_proof_of_account_is_verified_against_state_root() {
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+        repository
+            .replace(Address::ZERO, Account::empty().with_balance(U256::from(2)))
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+
+        let proof = repository.proof(&Address::ZERO, &[U256::one()]);
+
+        assert!(proof.verify(repository.state_root()).is_ok());
+        assert_eq!(U256::from(7), proof.storage_proof[0].value);
+    }
 }
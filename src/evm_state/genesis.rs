@@ -0,0 +1,365 @@
+//! Loader of the initial EVM state from a geth-style genesis file.
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{Address, EvmStateRepository, GenesisAlloc, InMemoryEvmStateRepository};
+//!
+//! let genesis = r#"{
+//!     "config": { "chainId": 1337 },
+//!     "alloc": {
+//!         "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed": { "balance": "1000000000000000000" }
+//!     }
+//! }"#;
+//! let mut repository = InMemoryEvmStateRepository::default();
+//!
+//! GenesisAlloc::from_json_str(genesis).unwrap().write_to(&mut repository).unwrap();
+//!
+//! let address: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
+//! assert!(repository.get(&address).unwrap().is_some());
+//! ```
+use crate::error::{Error, Result};
+use crate::evm_state::primitives::keccak256;
+use crate::evm_state::{
+    trie, Account, Address, Bytecode, EvmStateRepository, B256, EMPTY_CODE_HASH,
+};
+use primitive_types::U256;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// An account allocated in the genesis state.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GenesisAccount {
+    pub nonce: u64,
+    pub balance: U256,
+    /// Contract code, if the account is a contract.
+    pub code: Option<Bytecode>,
+    /// Contract storage slots and their values.
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// The `alloc` section of a geth-style `genesis.json` that allocates accounts of the genesis state.
+///
+/// Parsing follows geth, which accepts:
+/// * addresses with or without the `0x` prefix, in any letter case without checking their
+///   checksum,
+/// * balances and nonces as hex quantities, decimal strings or JSON numbers,
+/// * code as 0x-prefixed hex data,
+/// * storage slots and values as 0x-prefixed hex of up to 32 bytes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GenesisAlloc {
+    pub accounts: BTreeMap<Address, GenesisAccount>,
+}
+
+#[derive(Deserialize)]
+struct RawGenesisAccount {
+    balance: RawNumber,
+    #[serde(default)]
+    nonce: Option<RawNumber>,
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    storage: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawNumber {
+    Integer(u64),
+    String(String),
+}
+
+impl RawNumber {
+    fn parse(self) -> Result<U256> {
+        match self {
+            RawNumber::Integer(value) => Ok(U256::from(value)),
+            RawNumber::String(value) => match value.strip_prefix("0x") {
+                Some(digits) => parse_hex(digits),
+                None => U256::from_dec_str(&value).map_err(|_| invalid_number(&value)),
+            },
+        }
+    }
+}
+
+fn parse_hex(digits: &str) -> Result<U256> {
+    if digits.is_empty() || digits.len() > 64 {
+        return Err(invalid_number(digits));
+    }
+
+    U256::from_str_radix(digits, 16).map_err(|_| invalid_number(digits))
+}
+
+/// Parses an alloc key, which is case-insensitive, as geth does not validate its EIP-55 checksum.
+fn parse_address(value: &str) -> Result<Address> {
+    value
+        .to_ascii_lowercase()
+        .parse()
+        .map_err(|error| Error::invalid_input(format!("invalid address {value}: {error}")))
+}
+
+fn invalid_number(value: &str) -> Error {
+    Error::invalid_input(format!("invalid number: {value}"))
+}
+
+impl TryFrom<RawGenesisAccount> for GenesisAccount {
+    type Error = Error;
+
+    fn try_from(value: RawGenesisAccount) -> Result<Self> {
+        let nonce = match value.nonce {
+            Some(nonce) => u64::try_from(nonce.parse()?)
+                .map_err(|_| Error::invalid_input("nonce does not fit in 64 bits"))?,
+            None => 0,
+        };
+        let code = match value.code {
+            Some(code) => {
+                let digits = code.strip_prefix("0x").unwrap_or(&code);
+                let code = hex::decode(digits).map_err(Error::invalid_input)?;
+                Some(Bytecode::from(code)).filter(|code| !code.is_empty())
+            }
+            None => None,
+        };
+        let storage = value
+            .storage
+            .iter()
+            .map(|(slot, value)| {
+                let parse = |value: &str| parse_hex(value.strip_prefix("0x").unwrap_or(value));
+                Ok((parse(slot)?, parse(value)?))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            nonce,
+            balance: value.balance.parse()?,
+            code,
+            storage,
+        })
+    }
+}
+
+impl GenesisAlloc {
+    /// Reads the allocation from a JSON `reader` of either a whole `genesis.json` file or just its
+    /// `alloc` section.
+    pub fn from_json_reader(reader: impl Read) -> Result<Self> {
+        Self::from_json(serde_json::from_reader(reader).map_err(Error::invalid_input)?)
+    }
+
+    /// Reads the allocation from a JSON string of either a whole `genesis.json` file or just its
+    /// `alloc` section.
+    pub fn from_json_str(json: &str) -> Result<Self> {
+        Self::from_json(serde_json::from_str(json).map_err(Error::invalid_input)?)
+    }
+
+    fn from_json(mut json: Value) -> Result<Self> {
+        let alloc = match json.get_mut("alloc") {
+            Some(alloc) => alloc.take(),
+            None => json,
+        };
+        let alloc: BTreeMap<String, RawGenesisAccount> =
+            serde_json::from_value(alloc).map_err(Error::invalid_input)?;
+
+        Ok(Self {
+            accounts: alloc
+                .into_iter()
+                .map(|(address, account)| Ok((parse_address(&address)?, account.try_into()?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Writes all allocated accounts together with their code and storage into the `repository`.
+    ///
+    /// Code hashes are computed from the supplied code and storage roots from the supplied
+    /// storage.
+    pub fn write_to(&self, repository: &mut impl EvmStateRepository) -> Result<()> {
+        for (address, account) in &self.accounts {
+            let code_hash = match &account.code {
+                Some(code) => {
+                    let code_hash = B256::new(keccak256(code));
+                    repository.replace_code(code_hash, code.clone())?;
+                    code_hash
+                }
+                None => EMPTY_CODE_HASH,
+            };
+
+            repository.replace(
+                *address,
+                Account::new(
+                    account.nonce,
+                    account.balance,
+                    code_hash,
+                    trie::storage_root(&account.storage),
+                ),
+            )?;
+
+            for (slot, value) in &account.storage {
+                repository.replace_storage(*address, *slot, *value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::test_support::address;
+    use crate::evm_state::{InMemoryEvmStateRepository, EMPTY_ROOT_HASH};
+
+    const GENESIS: &str = r#"{
+        "config": { "chainId": 1337 },
+        "difficulty": "0x1",
+        "gasLimit": "0x1c9c380",
+        "alloc": {
+            "0x0000000000000000000000000000000000000001": {
+                "balance": "0xde0b6b3a7640000",
+                "nonce": "0x2"
+            },
+            "0000000000000000000000000000000000000002": {
+                "balance": "1000",
+                "code": "0x6000",
+                "storage": {
+                    "0x0000000000000000000000000000000000000000000000000000000000000001": "0x07",
+                    "0x02": "0x0000000000000000000000000000000000000000000000000000000000000008"
+                }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn test_accounts_allocated_in_genesis_are_found() {
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        GenesisAlloc::from_json_str(GENESIS)
+            .unwrap()
+            .write_to(&mut repository)
+            .unwrap();
+
+        let first_account = repository.get(&address(1)).unwrap().unwrap();
+        let second_account = repository.get(&address(2)).unwrap().unwrap();
+
+        assert_eq!(2, first_account.nonce());
+        assert_eq!(U256::exp10(18), first_account.balance());
+        assert_eq!(EMPTY_CODE_HASH, first_account.code_hash());
+        assert_eq!(0, second_account.nonce());
+        assert_eq!(U256::from(1000), second_account.balance());
+    }
+
+    #[test]
+    fn test_code_allocated_in_genesis_is_found_by_its_computed_hash() {
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        GenesisAlloc::from_json_str(GENESIS)
+            .unwrap()
+            .write_to(&mut repository)
+            .unwrap();
+
+        let account = repository.get(&address(2)).unwrap().unwrap();
+        let actual_code = repository.get_code(&account.code_hash()).unwrap();
+
+        assert_eq!(B256::new(keccak256([0x60, 0x00])), account.code_hash());
+        assert_eq!(Some(Bytecode::from(vec![0x60, 0x00])), actual_code);
+    }
+
+    #[test]
+    fn test_storage_allocated_in_genesis_is_read_back() {
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        GenesisAlloc::from_json_str(GENESIS)
+            .unwrap()
+            .write_to(&mut repository)
+            .unwrap();
+
+        let first_value = repository.get_storage(&address(2), &U256::one()).unwrap();
+        let second_value = repository.get_storage(&address(2), &U256::from(2)).unwrap();
+
+        assert_eq!(U256::from(7), first_value);
+        assert_eq!(U256::from(8), second_value);
+    }
+
+    #[test]
+    fn test_storage_root_of_account_allocated_in_genesis_is_computed() {
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        GenesisAlloc::from_json_str(GENESIS)
+            .unwrap()
+            .write_to(&mut repository)
+            .unwrap();
+
+        let first_account = repository.get(&address(1)).unwrap().unwrap();
+        let second_account = repository.get(&address(2)).unwrap().unwrap();
+
+        assert_eq!(EMPTY_ROOT_HASH, first_account.storage_root());
+        assert_ne!(EMPTY_ROOT_HASH, second_account.storage_root());
+    }
+
+    #[test]
+    fn test_state_root_of_rinkeby_genesis_matches_its_block_header() {
+        let genesis = include_str!("../../fixtures/genesis/rinkeby.json");
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        GenesisAlloc::from_json_str(genesis)
+            .unwrap()
+            .write_to(&mut repository)
+            .unwrap();
+
+        assert_eq!(
+            "0x53580584816f617295ea26c0e17641e0120cab2f0a8ffb53a866fd53aa8e8c2d"
+                .parse::<B256>()
+                .unwrap(),
+            repository.state_root()
+        );
+    }
+
+    #[test]
+    #[ignore = "requires fixtures/genesis/mainnet.json, which is not vendored yet"]
+    fn test_state_root_of_mainnet_genesis_matches_its_block_header() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/genesis/mainnet.json");
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        GenesisAlloc::from_json_reader(std::fs::File::open(path).unwrap())
+            .unwrap()
+            .write_to(&mut repository)
+            .unwrap();
+
+        assert_eq!(
+            "0xd7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544"
+                .parse::<B256>()
+                .unwrap(),
+            repository.state_root()
+        );
+    }
+
+    #[test]
+    fn test_address_with_invalid_checksum_is_allocated() {
+        let alloc = r#"{ "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed": { "balance": 5 } }"#;
+
+        let actual_alloc = GenesisAlloc::from_json_str(alloc).unwrap();
+
+        let address: Address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"
+            .parse()
+            .unwrap();
+        assert_eq!(U256::from(5), actual_alloc.accounts[&address].balance);
+    }
+
+    #[test]
+    fn test_bare_alloc_section_is_read() {
+        let alloc = r#"{ "0x0000000000000000000000000000000000000001": { "balance": 5 } }"#;
+
+        let actual_alloc = GenesisAlloc::from_json_reader(alloc.as_bytes()).unwrap();
+
+        assert_eq!(U256::from(5), actual_alloc.accounts[&address(1)].balance);
+    }
+
+    #[test]
+    fn test_genesis_with_invalid_balance_is_rejected() {
+        let alloc = r#"{ "0x0000000000000000000000000000000000000001": { "balance": "0xzz" } }"#;
+
+        let actual_alloc = GenesisAlloc::from_json_str(alloc);
+
+        assert!(
+            matches!(actual_alloc, Err(Error::InvalidInput(_))),
+            "Invalid balance accepted"
+        );
+    }
+}
@@ -1,41 +1,158 @@
 /// Simple, single-threaded in-memory implementation of [`EvmStateRepository`].
 ///
 /// All data is kept in-memory and accessed from a single thread.
-use crate::evm_state::{Account, Address, EvmStateRepository};
+use crate::error::Result;
+use crate::evm_state::{
+    check_code_hash, trie, Account, AccountProof, Address, Bytecode, EvmStateRepository,
+    EvmStateSnapshot, B256,
+};
+use primitive_types::U256;
 use std::collections::HashMap;
 
 /// In-memory single-threaded ideal for testing.
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct InMemoryEvmStateRepository {
     accounts: HashMap<Address, Account>,
+    storage: HashMap<Address, HashMap<U256, U256>>,
+    codes: HashMap<B256, Bytecode>,
+}
+
+impl InMemoryEvmStateRepository {
+    /// Creates a repository holding the whole state of the `snapshot`.
+    pub fn load(snapshot: EvmStateSnapshot) -> Self {
+        Self {
+            accounts: snapshot.accounts.into_iter().collect(),
+            storage: snapshot
+                .storage
+                .into_iter()
+                .map(|(address, slots)| (address, slots.into_iter().collect()))
+                .collect(),
+            codes: snapshot.codes.into_iter().collect(),
+        }
+    }
+
+    /// Takes a snapshot of the whole state held by the repository.
+    pub fn dump(&self) -> EvmStateSnapshot {
+        EvmStateSnapshot {
+            accounts: self.accounts.clone().into_iter().collect(),
+            storage: self
+                .storage
+                .iter()
+                .map(|(address, slots)| (*address, slots.clone().into_iter().collect()))
+                .collect(),
+            codes: self.codes.clone().into_iter().collect(),
+        }
+    }
+
+    /// Computes the state root of the whole state held by the repository.
+    ///
+    /// Storage roots of accounts are computed from their contract storage, regardless of the
+    /// storage roots held by the accounts.
+    pub fn state_root(&self) -> B256 {
+        trie::state_root(self.accounts_with_storage_roots())
+    }
+
+    /// Creates the proof of an account with the `address` and the requested `slots` of its
+    /// contract storage against the [state root](Self::state_root) of the repository.
+    pub fn proof(&self, address: &Address, slots: &[U256]) -> AccountProof {
+        let storage = self.storage.get(address);
+        let account = self.accounts.get(address).map(|account| {
+            let storage_root = trie::storage_root(storage.into_iter().flatten());
+            account.clone().with_storage_root(storage_root)
+        });
+
+        AccountProof::new(
+            address,
+            account,
+            self.accounts_with_storage_roots(),
+            storage,
+            slots,
+        )
+    }
+
+    fn accounts_with_storage_roots(&self) -> impl Iterator<Item = (Address, Account)> + '_ {
+        self.accounts.iter().map(|(address, account)| {
+            let storage_root = trie::storage_root(self.storage.get(address).into_iter().flatten());
+            (*address, account.clone().with_storage_root(storage_root))
+        })
+    }
 }
 
 impl EvmStateRepository for InMemoryEvmStateRepository {
-    fn get(&self, address: &Address) -> Option<Account> {
-        self.accounts.get(address).cloned()
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        Ok(self.accounts.get(address).cloned())
     }
 
-    fn replace(&mut self, address: Address, account: Account) {
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
         self.accounts.insert(address, account);
+
+        Ok(())
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        self.accounts.remove(address);
+        self.storage.remove(address);
+
+        Ok(())
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        Ok(self
+            .storage
+            .get(address)
+            .and_then(|storage| storage.get(slot))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        Ok(self
+            .storage
+            .get(address)
+            .into_iter()
+            .flatten()
+            .filter(|(_slot, value)| !value.is_zero())
+            .map(|(slot, value)| (*slot, *value))
+            .collect())
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        self.storage.entry(address).or_default().insert(slot, value);
+
+        Ok(())
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        Ok(self.codes.get(code_hash).cloned())
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        check_code_hash(&code_hash, &code)?;
+        self.codes.insert(code_hash, code);
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use primitive_types::{H160, U256};
+    use crate::evm_state::primitives::keccak256;
+    use primitive_types::H160;
 
     #[test]
     fn test_account_by_existent_address_from_repository_is_found() {
         let mut repository = InMemoryEvmStateRepository::default();
 
-        repository.replace(
-            Address::from(H160::zero()),
-            Account::new(0, U256::zero(), U256::zero(), U256::zero()),
-        );
+        repository
+            .replace(
+                Address::from(H160::zero()),
+                Account::new(0, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
 
-        let actual_account = repository.get(&Address::from(H160::zero()));
-        let expected_account = Account::new(0, U256::zero(), U256::zero(), U256::zero());
+        let actual_account = repository.get(&Address::from(H160::zero())).unwrap();
+        let expected_account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
 
         assert!(actual_account.is_some(), "Account not found");
 
@@ -48,11 +165,129 @@ mod tests {
     fn test_account_by_non_existent_address_from_repository_is_not_found() {
         let repository = InMemoryEvmStateRepository::default();
 
-        let actual_account = repository.get(&Address::from(H160::zero()));
+        let actual_account = repository.get(&Address::from(H160::zero())).unwrap();
 
         assert!(
             actual_account.is_none(),
             "Account found but none was present"
         );
     }
+
+    #[test]
+    fn test_storage_slot_written_to_repository_is_read_back() {
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        repository
+            .replace_storage(Address::from(H160::zero()), U256::one(), U256::from(7))
+            .unwrap();
+
+        let actual_value = repository
+            .get_storage(&Address::from(H160::zero()), &U256::one())
+            .unwrap();
+
+        assert_eq!(U256::from(7), actual_value);
+    }
+
+    #[test]
+    fn test_storage_slot_never_written_to_repository_reads_as_zero() {
+        let repository = InMemoryEvmStateRepository::default();
+
+        let actual_value = repository
+            .get_storage(&Address::from(H160::zero()), &U256::one())
+            .unwrap();
+
+        assert_eq!(U256::zero(), actual_value);
+    }
+
+    #[test]
+    fn test_code_by_existent_hash_from_repository_is_found() {
+        let code_hash = B256::new(keccak256([0x60, 0x00]));
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        repository
+            .replace_code(code_hash, Bytecode::from(vec![0x60, 0x00]))
+            .unwrap();
+
+        let actual_code = repository.get_code(&code_hash).unwrap();
+
+        assert_eq!(Some(Bytecode::from(vec![0x60, 0x00])), actual_code);
+    }
+
+    #[test]
+    fn test_deleted_account_is_not_found_and_its_storage_is_cleared() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace(
+                Address::from(H160::zero()),
+                Account::new(0, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
+        repository
+            .replace_storage(Address::from(H160::zero()), U256::one(), U256::from(7))
+            .unwrap();
+
+        repository.delete(&Address::from(H160::zero())).unwrap();
+
+        let actual_account = repository.get(&Address::from(H160::zero())).unwrap();
+        let actual_value = repository
+            .get_storage(&Address::from(H160::zero()), &U256::one())
+            .unwrap();
+
+        assert!(actual_account.is_none(), "Deleted account found");
+        assert_eq!(U256::zero(), actual_value);
+    }
+
+    #[test]
+    fn test_state_dumped_from_repository_is_loaded_back() {
+        let code_hash = B256::new(keccak256([0x60, 0x00]));
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace(
+                Address::ZERO,
+                Account::new(1, U256::from(2), code_hash, B256::ZERO),
+            )
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+        repository
+            .replace_code(code_hash, Bytecode::from(vec![0x60, 0x00]))
+            .unwrap();
+
+        let actual_repository = InMemoryEvmStateRepository::load(repository.dump());
+
+        assert_eq!(repository, actual_repository);
+    }
+
+    #[test]
+    fn test_state_root_of_repository_matches_state_root_of_its_dump() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository
+            .replace(Address::ZERO, Account::empty().with_balance(U256::from(2)))
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+
+        let actual_root = repository.state_root();
+
+        assert_eq!(repository.dump().state_root(), actual_root);
+        assert_ne!(
+            InMemoryEvmStateRepository::default().state_root(),
+            actual_root
+        );
+    }
+
+    #[test]
+    fn test_state_root_does_not_depend_on_storage_slots_holding_zero() {
+        let mut repository = InMemoryEvmStateRepository::default();
+        repository.replace(Address::ZERO, Account::empty()).unwrap();
+        let expected_root = repository.state_root();
+
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::zero())
+            .unwrap();
+
+        assert_eq!(expected_root, repository.state_root());
+    }
 }
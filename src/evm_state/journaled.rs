@@ -0,0 +1,565 @@
+//! Transactional writes to an [`EvmStateRepository`] that can be reverted to nested checkpoints.
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{
+//!     Account, Address, EvmStateRepository, InMemoryEvmStateRepository,
+//!     JournaledEvmStateRepository,
+//! };
+//! use primitive_types::U256;
+//!
+//! let mut repository = JournaledEvmStateRepository::new(InMemoryEvmStateRepository::default());
+//! repository.replace(Address::ZERO, Account::empty().with_nonce(1)).unwrap();
+//!
+//! let checkpoint = repository.checkpoint();
+//! repository.replace(Address::ZERO, Account::empty().with_nonce(2)).unwrap();
+//! repository.revert_to(checkpoint);
+//! repository.commit().unwrap();
+//!
+//! let inner = repository.into_inner();
+//! assert_eq!(1, inner.get(&Address::ZERO).unwrap().unwrap().nonce());
+//! ```
+use crate::error::Result;
+use crate::evm_state::write_back::DirtyState;
+use crate::evm_state::{
+    check_code_hash, Account, Address, Bytecode, Changeset, EvmStateRepository, B256,
+};
+use primitive_types::U256;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A position in the journal of [`JournaledEvmStateRepository`] that writes can be reverted to.
+///
+/// Checkpoints are ordered, so a checkpoint taken later, e.g. by a nested call frame, is greater.
+/// Positions count every write since the repository was created, so a checkpoint taken before a
+/// commit never points at writes made after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Checkpoint(usize);
+
+/// A write recorded together with what it overwrote, so that it can be undone.
+#[derive(Debug)]
+enum JournalEntry {
+    Account {
+        address: Address,
+        previous: Option<Option<Account>>,
+    },
+    Delete {
+        address: Address,
+        previous: Option<Option<Account>>,
+        was_deleted: bool,
+        storage: Vec<(U256, U256)>,
+    },
+    Storage {
+        address: Address,
+        slot: U256,
+        previous: Option<U256>,
+    },
+    Code {
+        code_hash: B256,
+        previous: Option<Bytecode>,
+    },
+}
+
+impl JournalEntry {
+    fn undo(self, overlay: &mut DirtyState) {
+        match self {
+            Self::Account { address, previous } => {
+                restore(&mut overlay.accounts, address, previous)
+            }
+            Self::Delete {
+                address,
+                previous,
+                was_deleted,
+                storage,
+            } => {
+                restore(&mut overlay.accounts, address, previous);
+                if !was_deleted {
+                    overlay.deleted.remove(&address);
+                }
+                overlay.storage.extend(
+                    storage
+                        .into_iter()
+                        .map(|(slot, value)| ((address, slot), value)),
+                );
+            }
+            Self::Storage {
+                address,
+                slot,
+                previous,
+            } => restore(&mut overlay.storage, (address, slot), previous),
+            Self::Code {
+                code_hash,
+                previous,
+            } => restore(&mut overlay.codes, code_hash, previous),
+        }
+    }
+}
+
+/// Restores the `previous` value of the `key`, where [`None`] stands for no value.
+fn restore<K: Eq + Hash, V>(map: &mut HashMap<K, V>, key: K, previous: Option<V>) {
+    match previous {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+/// An [`EvmStateRepository`] that keeps writes in an overlay over the inner repository until they
+/// are committed, e.g. while a transaction is executed.
+///
+/// Every write is recorded in a journal, so that writes made after a [`Checkpoint`] can be reverted
+/// with [`JournaledEvmStateRepository::revert_to`], as happens when a call frame reverts. Reads
+/// see the overlay first and read through to the inner repository for untouched state.
+/// [`JournaledEvmStateRepository::commit`] writes the overlay into the inner repository, while
+/// dropping the repository discards it.
+#[derive(Debug)]
+pub struct JournaledEvmStateRepository<R: EvmStateRepository> {
+    inner: R,
+    overlay: DirtyState,
+    journal: Vec<JournalEntry>,
+    /// Number of writes committed or discarded so far, which is the position of the journal start.
+    base: usize,
+}
+
+impl<R: EvmStateRepository> JournaledEvmStateRepository<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            overlay: DirtyState::default(),
+            journal: Vec::new(),
+            base: 0,
+        }
+    }
+
+    /// Returns a checkpoint of the current state, which writes made afterwards can be reverted to.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.base + self.journal.len())
+    }
+
+    /// Undoes every write made after the `checkpoint` was taken, in reverse order.
+    ///
+    /// Checkpoints taken after the `checkpoint` are reverted as well and must not be used anymore.
+    /// Reverting to a checkpoint taken before the last commit or discard does nothing, as
+    /// committed writes cannot be reverted.
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) {
+        let Some(start) = checkpoint.0.checked_sub(self.base) else {
+            return;
+        };
+        let start = start.min(self.journal.len());
+        for entry in self.journal.drain(start..).rev() {
+            entry.undo(&mut self.overlay);
+        }
+    }
+
+    /// Writes every write made so far into the inner repository and clears the journal, so that
+    /// checkpoints taken so far can no longer be reverted.
+    ///
+    /// Nothing is cleared on failure and the commit can be retried.
+    pub fn commit(&mut self) -> Result<()> {
+        self.overlay.flush_into(&mut self.inner)?;
+        self.clear_journal();
+
+        Ok(())
+    }
+
+    /// Discards every write made since the last commit.
+    pub fn discard(&mut self) {
+        self.overlay = DirtyState::default();
+        self.clear_journal();
+    }
+
+    /// Returns changes of accounts and storage made since the last commit, compared with the inner
+    /// repository.
+    ///
+    /// Taken right before [`JournaledEvmStateRepository::commit`], it holds the changes the commit
+    /// makes, which can be undone later by applying its inverse. Storage of deleted accounts is
+    /// enumerated by [`EvmStateRepository::get_all_storage`] of the inner repository.
+    pub fn changeset(&self) -> Result<Changeset> {
+        self.overlay.changeset(&self.inner)
+    }
+
+    /// Returns a reference to the inner repository, which holds committed writes only.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the inner repository, discarding writes that have not been committed.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn clear_journal(&mut self) {
+        self.base += self.journal.len();
+        self.journal.clear();
+    }
+
+    fn replace_account(&mut self, address: Address, account: Account) {
+        let previous = self.overlay.accounts.insert(address, Some(account));
+        self.journal
+            .push(JournalEntry::Account { address, previous });
+    }
+}
+
+impl<R: EvmStateRepository> EvmStateRepository for JournaledEvmStateRepository<R> {
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        match self.overlay.accounts.get(address) {
+            Some(account) => Ok(account.clone()),
+            None => self.inner.get(address),
+        }
+    }
+
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        self.overlay.get_many(&self.inner, addresses)
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        self.replace_account(address, account);
+
+        Ok(())
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        for (address, account) in accounts {
+            self.replace_account(address, account);
+        }
+
+        Ok(())
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        let previous = self.overlay.accounts.get(address).cloned();
+        let was_deleted = self.overlay.deleted.contains(address);
+        let storage = self
+            .overlay
+            .storage
+            .iter()
+            .filter(|((slot_address, _slot), _value)| slot_address == address)
+            .map(|((_address, slot), value)| (*slot, *value))
+            .collect();
+        self.overlay.delete(address);
+        self.journal.push(JournalEntry::Delete {
+            address: *address,
+            previous,
+            was_deleted,
+            storage,
+        });
+
+        Ok(())
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        match self.overlay.get_storage(address, slot) {
+            Some(value) => Ok(value),
+            None => self.inner.get_storage(address, slot),
+        }
+    }
+
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        let storage = self.inner.get_all_storage(address)?;
+
+        Ok(self.overlay.overlay_storage(address, storage))
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        let previous = self.overlay.storage.insert((address, slot), value);
+        self.journal.push(JournalEntry::Storage {
+            address,
+            slot,
+            previous,
+        });
+
+        Ok(())
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        match self.overlay.codes.get(code_hash) {
+            Some(code) => Ok(Some(code.clone())),
+            None => self.inner.get_code(code_hash),
+        }
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        check_code_hash(&code_hash, &code)?;
+        let previous = self.overlay.codes.insert(code_hash, code);
+        self.journal.push(JournalEntry::Code {
+            code_hash,
+            previous,
+        });
+
+        Ok(())
+    }
+
+    /// Flushes the inner repository. Writes that have not been committed are not flushed, as they
+    /// can still be reverted.
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::primitives::keccak256;
+    use crate::evm_state::test_support::{account, address};
+    use crate::evm_state::{InMemoryEvmStateRepository, StorageChange};
+
+    fn repository() -> JournaledEvmStateRepository<InMemoryEvmStateRepository> {
+        let mut inner = InMemoryEvmStateRepository::default();
+        inner.replace(address(1), account(1)).unwrap();
+        inner
+            .replace_storage(address(1), U256::one(), U256::from(7))
+            .unwrap();
+
+        JournaledEvmStateRepository::new(inner)
+    }
+
+    #[test]
+    fn test_untouched_account_is_read_through_inner_repository() {
+        let repository = repository();
+
+        let actual_account = repository.get(&address(1)).unwrap();
+        let actual_value = repository.get_storage(&address(1), &U256::one()).unwrap();
+
+        assert_eq!(Some(account(1)), actual_account);
+        assert_eq!(U256::from(7), actual_value);
+    }
+
+    #[test]
+    fn test_uncommitted_write_is_read_from_overlay_but_not_written_to_inner_repository() {
+        let mut repository = repository();
+
+        repository.replace(address(1), account(2)).unwrap();
+
+        assert_eq!(Some(account(2)), repository.get(&address(1)).unwrap());
+        assert_eq!(Some(account(1)), repository.inner.get(&address(1)).unwrap());
+    }
+
+    #[test]
+    fn test_writes_after_checkpoint_are_reverted() {
+        let code_hash = B256::new(keccak256([0x00]));
+        let mut repository = repository();
+        repository.replace(address(2), account(2)).unwrap();
+
+        let checkpoint = repository.checkpoint();
+        repository.replace(address(1), account(3)).unwrap();
+        repository.replace(address(2), account(4)).unwrap();
+        repository
+            .replace_storage(address(1), U256::one(), U256::from(8))
+            .unwrap();
+        repository
+            .replace_code(code_hash, Bytecode::from(vec![0x00]))
+            .unwrap();
+        repository.revert_to(checkpoint);
+
+        assert_eq!(Some(account(1)), repository.get(&address(1)).unwrap());
+        assert_eq!(Some(account(2)), repository.get(&address(2)).unwrap());
+        assert_eq!(
+            U256::from(7),
+            repository.get_storage(&address(1), &U256::one()).unwrap()
+        );
+        assert_eq!(None, repository.get_code(&code_hash).unwrap());
+    }
+
+    #[test]
+    fn test_reverting_to_outer_checkpoint_reverts_nested_checkpoints() {
+        let mut repository = repository();
+
+        let outer_checkpoint = repository.checkpoint();
+        repository.replace(address(1), account(2)).unwrap();
+        let inner_checkpoint = repository.checkpoint();
+        repository.replace(address(1), account(3)).unwrap();
+
+        repository.revert_to(inner_checkpoint);
+        assert_eq!(Some(account(2)), repository.get(&address(1)).unwrap());
+
+        repository.revert_to(outer_checkpoint);
+        assert_eq!(Some(account(1)), repository.get(&address(1)).unwrap());
+    }
+
+    #[test]
+    fn test_reverted_delete_restores_account_and_its_storage() {
+        let mut repository = repository();
+        repository
+            .replace_storage(address(1), U256::from(2), U256::from(9))
+            .unwrap();
+
+        let checkpoint = repository.checkpoint();
+        repository.delete(&address(1)).unwrap();
+        assert_eq!(None, repository.get(&address(1)).unwrap());
+        assert_eq!(
+            U256::zero(),
+            repository.get_storage(&address(1), &U256::one()).unwrap()
+        );
+        repository.revert_to(checkpoint);
+
+        assert_eq!(Some(account(1)), repository.get(&address(1)).unwrap());
+        assert_eq!(
+            U256::from(7),
+            repository.get_storage(&address(1), &U256::one()).unwrap()
+        );
+        assert_eq!(
+            U256::from(9),
+            repository.get_storage(&address(1), &U256::from(2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_committed_writes_are_written_to_inner_repository() {
+        let mut repository = repository();
+        repository.replace(address(2), account(2)).unwrap();
+        repository.delete(&address(1)).unwrap();
+
+        repository.commit().unwrap();
+
+        let inner = repository.into_inner();
+        assert_eq!(Some(account(2)), inner.get(&address(2)).unwrap());
+        assert_eq!(None, inner.get(&address(1)).unwrap());
+        assert_eq!(
+            U256::zero(),
+            inner.get_storage(&address(1), &U256::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_committed_writes_are_not_reverted() {
+        let mut repository = repository();
+        let checkpoint = repository.checkpoint();
+        repository.replace(address(1), account(2)).unwrap();
+
+        repository.commit().unwrap();
+        repository.revert_to(checkpoint);
+
+        assert_eq!(Some(account(2)), repository.get(&address(1)).unwrap());
+    }
+
+    #[test]
+    fn test_reverting_to_checkpoint_taken_before_commit_keeps_later_writes() {
+        let mut repository = repository();
+        let checkpoint = repository.checkpoint();
+        repository.replace(address(1), account(2)).unwrap();
+        repository.commit().unwrap();
+        repository.replace(address(2), account(2)).unwrap();
+        repository.replace(address(3), account(3)).unwrap();
+
+        repository.revert_to(checkpoint);
+
+        assert_eq!(Some(account(2)), repository.get(&address(2)).unwrap());
+        assert_eq!(Some(account(3)), repository.get(&address(3)).unwrap());
+    }
+
+    #[test]
+    fn test_changeset_holds_changes_since_last_commit() {
+        let mut repository = repository();
+        repository.replace(address(2), account(2)).unwrap();
+        repository.commit().unwrap();
+        repository.replace(address(1), account(3)).unwrap();
+        repository.replace(address(2), account(2)).unwrap();
+        repository.delete(&address(2)).unwrap();
+        repository.replace(address(3), account(3)).unwrap();
+        repository.delete(&address(3)).unwrap();
+
+        let changeset = repository.changeset().unwrap();
+
+        assert_eq!(2, changeset.len());
+        assert_eq!(Some(account(1)), changeset.accounts[&address(1)].before);
+        assert_eq!(Some(account(3)), changeset.accounts[&address(1)].after);
+        assert_eq!(Some(account(2)), changeset.accounts[&address(2)].before);
+        assert_eq!(None, changeset.accounts[&address(2)].after);
+    }
+
+    #[test]
+    fn test_changeset_holds_storage_written_before_delete() {
+        let mut repository = repository();
+        repository
+            .replace_storage(address(1), U256::one(), U256::from(8))
+            .unwrap();
+        repository
+            .replace_storage(address(1), U256::from(2), U256::from(3))
+            .unwrap();
+        repository.delete(&address(1)).unwrap();
+        repository
+            .replace_storage(address(2), U256::one(), U256::from(4))
+            .unwrap();
+
+        let changeset = repository.changeset().unwrap();
+
+        assert_eq!(3, changeset.len());
+        assert_eq!(
+            StorageChange {
+                before: U256::from(7),
+                after: U256::zero()
+            },
+            changeset.storage[&address(1)][&U256::one()]
+        );
+        assert_eq!(
+            StorageChange {
+                before: U256::zero(),
+                after: U256::from(4)
+            },
+            changeset.storage[&address(2)][&U256::one()]
+        );
+    }
+
+    #[test]
+    fn test_inverse_changeset_of_delete_restores_storage_of_inner_repository() {
+        let mut repository = repository();
+        repository.delete(&address(1)).unwrap();
+        let changeset = repository.changeset().unwrap();
+        repository.commit().unwrap();
+        let mut inner = repository.into_inner();
+
+        changeset.inverse().apply_to(&mut inner).unwrap();
+
+        assert_eq!(Some(account(1)), inner.get(&address(1)).unwrap());
+        assert_eq!(
+            U256::from(7),
+            inner.get_storage(&address(1), &U256::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_all_storage_is_read_through_overlay() {
+        let mut repository = repository();
+        repository
+            .replace_storage(address(1), U256::from(2), U256::from(3))
+            .unwrap();
+        let mut storage_before_delete = repository.get_all_storage(&address(1)).unwrap();
+        storage_before_delete.sort();
+        repository.delete(&address(1)).unwrap();
+        repository
+            .replace_storage(address(1), U256::from(3), U256::from(4))
+            .unwrap();
+
+        let storage_after_delete = repository.get_all_storage(&address(1)).unwrap();
+
+        assert_eq!(
+            vec![(U256::one(), U256::from(7)), (U256::from(2), U256::from(3))],
+            storage_before_delete
+        );
+        assert_eq!(vec![(U256::from(3), U256::from(4))], storage_after_delete);
+    }
+
+    #[test]
+    fn test_discarded_writes_are_not_committed() {
+        let mut repository = repository();
+        repository.replace(address(1), account(2)).unwrap();
+
+        repository.discard();
+        repository.commit().unwrap();
+
+        assert_eq!(Some(account(1)), repository.get(&address(1)).unwrap());
+        assert_eq!(
+            Some(account(1)),
+            repository.inner().get(&address(1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_uncommitted_writes_are_discarded() {
+        let mut repository = repository();
+        repository.replace(address(1), account(2)).unwrap();
+
+        let inner = repository.into_inner();
+
+        assert_eq!(Some(account(1)), inner.get(&address(1)).unwrap());
+    }
+}
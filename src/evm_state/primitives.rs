@@ -0,0 +1,340 @@
+//! Fixed-size byte types identifying entities of the EVM state.
+use primitive_types::{H160, H256, U256};
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+use tiny_keccak::{Hasher, Keccak};
+
+/// An Ethereum [address] uniquely identifies [`Account`].
+///
+/// It is displayed as a hex string with an [EIP-55] mixed-case checksum and parsed from a hex
+/// string either in a single case or with a valid checksum.
+///
+/// # Example
+/// ```
+/// use evm_state_cache::Address;
+///
+/// let address: Address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".parse().unwrap();
+///
+/// assert_eq!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", address.to_string());
+/// ```
+///
+/// [address]: https://ethereum.org/en/glossary/#address
+/// [`Account`]: crate::Account
+/// [EIP-55]: https://eips.ethereum.org/EIPS/eip-55
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address([u8; 20]);
+
+/// A 256-bit hash, such as a code hash or a storage root of [`Account`].
+///
+/// It is displayed and parsed as a lowercase hex string.
+///
+/// [`Account`]: crate::Account
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct B256([u8; 32]);
+
+/// An error returned when parsing [`Address`] or [`B256`] from a hex string fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseHexError {
+    /// The string does not have the expected number of hex digits.
+    InvalidLength,
+    /// The string contains a character that is not a hex digit.
+    InvalidCharacter,
+    /// The mixed-case string does not match its EIP-55 checksum.
+    InvalidChecksum,
+}
+
+impl fmt::Display for ParseHexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseHexError::InvalidLength => f.write_str("invalid hex string length"),
+            ParseHexError::InvalidCharacter => f.write_str("invalid hex character"),
+            ParseHexError::InvalidChecksum => f.write_str("invalid EIP-55 checksum"),
+        }
+    }
+}
+
+impl StdError for ParseHexError {}
+
+/// Computes the Keccak-256 hash of `bytes`.
+pub(crate) fn keccak256(bytes: impl AsRef<[u8]>) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    let mut hash = [0u8; 32];
+    hasher.update(bytes.as_ref());
+    hasher.finalize(&mut hash);
+    hash
+}
+
+/// Decodes a hex string with an optional `0x` prefix into exactly `N` bytes.
+fn decode_hex<const N: usize>(value: &str) -> Result<[u8; N], ParseHexError> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    let mut bytes = [0u8; N];
+
+    hex::decode_to_slice(value, &mut bytes).map_err(|error| match error {
+        hex::FromHexError::InvalidHexCharacter { .. } => ParseHexError::InvalidCharacter,
+        hex::FromHexError::OddLength | hex::FromHexError::InvalidStringLength => {
+            ParseHexError::InvalidLength
+        }
+    })?;
+
+    Ok(bytes)
+}
+
+impl Address {
+    /// An address of all zero bytes.
+    pub const ZERO: Self = Self([0u8; 20]);
+
+    pub const fn new(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Returns the address as a hex string with an EIP-55 mixed-case checksum.
+    pub fn to_checksum(&self) -> String {
+        let hex = hex::encode(self.0);
+        let hash = keccak256(&hex);
+
+        let checksummed: String = hex
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                // A letter is uppercased when the corresponding nibble of the hash is at least 8
+                let nibble = hash[i / 2] >> (4 * (1 - i % 2)) & 0x0f;
+                if nibble >= 8 {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                }
+            })
+            .collect();
+
+        format!("0x{checksummed}")
+    }
+}
+
+impl B256 {
+    /// A hash of all zero bytes.
+    pub const ZERO: Self = Self([0u8; 32]);
+
+    pub const fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl FromStr for Address {
+    type Err = ParseHexError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let address = Self(decode_hex(value)?);
+        let digits = value.strip_prefix("0x").unwrap_or(value);
+
+        let is_mixed_case = digits.chars().any(|c| c.is_ascii_uppercase())
+            && digits.chars().any(|c| c.is_ascii_lowercase());
+
+        if is_mixed_case && address.to_checksum()[2..] != *digits {
+            return Err(ParseHexError::InvalidChecksum);
+        }
+
+        Ok(address)
+    }
+}
+
+impl FromStr for B256 {
+    type Err = ParseHexError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        decode_hex(value).map(Self)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_checksum())
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Display for B256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for B256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for B256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for B256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; 20]> for Address {
+    fn from(value: [u8; 20]) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Address> for [u8; 20] {
+    fn from(value: Address) -> Self {
+        value.0
+    }
+}
+
+impl From<H160> for Address {
+    fn from(value: H160) -> Self {
+        Self(value.0)
+    }
+}
+
+impl From<Address> for H160 {
+    fn from(value: Address) -> Self {
+        H160(value.0)
+    }
+}
+
+impl From<[u8; 32]> for B256 {
+    fn from(value: [u8; 32]) -> Self {
+        Self(value)
+    }
+}
+
+impl From<B256> for [u8; 32] {
+    fn from(value: B256) -> Self {
+        value.0
+    }
+}
+
+impl From<H256> for B256 {
+    fn from(value: H256) -> Self {
+        Self(value.0)
+    }
+}
+
+impl From<B256> for H256 {
+    fn from(value: B256) -> Self {
+        H256(value.0)
+    }
+}
+
+/// Converts a [`U256`] into its big-endian bytes.
+impl From<U256> for B256 {
+    fn from(value: U256) -> Self {
+        let mut bytes = [0u8; 32];
+        value.to_big_endian(&mut bytes);
+        Self(bytes)
+    }
+}
+
+/// Interprets the bytes as a big-endian [`U256`].
+impl From<B256> for U256 {
+    fn from(value: B256) -> Self {
+        U256::from_big_endian(&value.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_of_no_bytes_is_empty_code_hash() {
+        assert_eq!(crate::evm_state::EMPTY_CODE_HASH, B256::new(keccak256([])));
+    }
+
+    #[test]
+    fn test_address_is_displayed_with_checksum() {
+        // Test vectors of EIP-55
+        for expected in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let address: Address = expected.to_lowercase().parse().unwrap();
+
+            assert_eq!(expected, address.to_string());
+        }
+    }
+
+    #[test]
+    fn test_address_with_valid_checksum_is_parsed() {
+        let actual_address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse::<Address>();
+
+        assert!(actual_address.is_ok(), "Checksummed address not parsed");
+    }
+
+    #[test]
+    fn test_address_with_invalid_checksum_is_not_parsed() {
+        let actual_address = "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse::<Address>();
+
+        assert_eq!(Err(ParseHexError::InvalidChecksum), actual_address);
+    }
+
+    #[test]
+    fn test_address_of_invalid_length_is_not_parsed() {
+        let actual_address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea".parse::<Address>();
+
+        assert_eq!(Err(ParseHexError::InvalidLength), actual_address);
+    }
+
+    #[test]
+    fn test_hash_is_displayed_as_lowercase_hex_and_parsed_back() {
+        let hash = B256::from(U256::from(0xabcdef));
+
+        let actual_hash = hash.to_string().parse::<B256>().unwrap();
+
+        assert_eq!(
+            "0x0000000000000000000000000000000000000000000000000000000000abcdef",
+            hash.to_string()
+        );
+        assert_eq!(hash, actual_hash);
+    }
+
+    #[test]
+    fn test_hash_with_non_hex_character_is_not_parsed() {
+        let actual_hash = format!("0x{}", "g".repeat(64)).parse::<B256>();
+
+        assert_eq!(Err(ParseHexError::InvalidCharacter), actual_hash);
+    }
+}
@@ -0,0 +1,321 @@
+//! Merkle proofs of accounts and their contract storage shaped as the output of [eth_getProof].
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{Account, Address, EvmStateRepository, InMemoryEvmStateRepository};
+//! use primitive_types::U256;
+//!
+//! let mut repository = InMemoryEvmStateRepository::default();
+//! repository.replace(Address::ZERO, Account::empty().with_nonce(1)).unwrap();
+//! repository.replace_storage(Address::ZERO, U256::one(), U256::from(7)).unwrap();
+//!
+//! let proof = repository.proof(&Address::ZERO, &[U256::one()]);
+//!
+//! assert!(proof.verify(repository.state_root()).is_ok());
+//! assert_eq!(U256::from(7), proof.storage_proof[0].value);
+//! ```
+//!
+//! [eth_getProof]: https://eips.ethereum.org/EIPS/eip-1186
+use crate::error::{Error, Result};
+use crate::evm_state::{codec, trie, Account, Address, B256};
+use primitive_types::U256;
+use std::collections::HashMap;
+
+/// A Merkle proof of an account in the state trie together with proofs of requested slots of its
+/// contract storage.
+///
+/// A non-existent account is proven to be absent and has the fields of an empty account.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "camelCase")
+)]
+pub struct AccountProof {
+    pub address: Address,
+    /// Encoded trie nodes on the path from the state root to the account.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::data_list")
+    )]
+    pub account_proof: Vec<Vec<u8>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::quantity")
+    )]
+    pub balance: U256,
+    pub code_hash: B256,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::quantity")
+    )]
+    pub nonce: u64,
+    /// The storage root of the account.
+    pub storage_hash: B256,
+    pub storage_proof: Vec<StorageProof>,
+}
+
+/// A Merkle proof of a slot in the storage trie of an account.
+///
+/// A slot holding zero is proven to be absent.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StorageProof {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::quantity")
+    )]
+    pub key: U256,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::quantity")
+    )]
+    pub value: U256,
+    /// Encoded trie nodes on the path from the storage root to the slot.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::evm_state::serialization::data_list")
+    )]
+    pub proof: Vec<Vec<u8>>,
+}
+
+impl AccountProof {
+    /// Creates the proof of an `account` with the `address` and its storage `slots`, where
+    /// `accounts` make the whole state and `storage` is the contract storage of the account.
+    ///
+    /// Storage roots of the `account` and `accounts` must be computed beforehand.
+    pub(crate) fn new(
+        address: &Address,
+        account: Option<Account>,
+        accounts: impl IntoIterator<Item = (Address, Account)>,
+        storage: Option<&HashMap<U256, U256>>,
+        slots: &[U256],
+    ) -> Self {
+        let account = account.unwrap_or_else(Account::empty);
+        let storage_proof = slots
+            .iter()
+            .map(|slot| StorageProof {
+                key: *slot,
+                value: storage
+                    .and_then(|storage| storage.get(slot))
+                    .copied()
+                    .unwrap_or_default(),
+                proof: trie::storage_proof(storage.into_iter().flatten(), slot),
+            })
+            .collect();
+
+        Self {
+            address: *address,
+            account_proof: trie::state_proof(accounts, address),
+            balance: account.balance(),
+            code_hash: account.code_hash(),
+            nonce: account.nonce(),
+            storage_hash: account.storage_root(),
+            storage_proof,
+        }
+    }
+
+    /// Verifies the proof of the account and all proofs of its storage slots against the
+    /// `state_root`.
+    ///
+    /// Returns [`Error::InvalidProof`] if any proof is malformed or proves different values than
+    /// the ones it holds.
+    pub fn verify(&self, state_root: B256) -> Result<()> {
+        let account = Account::new(self.nonce, self.balance, self.code_hash, self.storage_hash);
+        let proven_account = trie::verify_proof(
+            state_root,
+            &trie::state_key(&self.address),
+            &self.account_proof,
+        )?
+        .map(|value| rlp::decode::<Account>(&value).map_err(Error::invalid_proof))
+        .transpose()?;
+
+        match proven_account {
+            Some(proven_account) if proven_account == account => {}
+            None if account == Account::empty() => {}
+            _ => return Err(Error::invalid_proof("account does not match its proof")),
+        }
+
+        for storage_proof in &self.storage_proof {
+            storage_proof.verify(self.storage_hash)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StorageProof {
+    /// Verifies the proof of the slot against the `storage_root` of its account.
+    ///
+    /// Returns [`Error::InvalidProof`] if the proof is malformed or proves a different value than
+    /// the one it holds.
+    pub fn verify(&self, storage_root: B256) -> Result<()> {
+        let proven_value =
+            trie::verify_proof(storage_root, &trie::storage_key(&self.key), &self.proof)?;
+        let proven_value = match proven_value {
+            Some(value) => {
+                codec::decode_u256(&rlp::Rlp::new(&value)).map_err(Error::invalid_proof)?
+            }
+            None => U256::zero(),
+        };
+
+        if proven_value != self.value {
+            return Err(Error::invalid_proof(
+                "storage value does not match its proof",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::test_support::address;
+    use crate::evm_state::{EvmStateRepository, InMemoryEvmStateRepository, EMPTY_ROOT_HASH};
+
+    fn repository() -> InMemoryEvmStateRepository {
+        let mut repository = InMemoryEvmStateRepository::default();
+
+        for i in 0..32u8 {
+            repository
+                .replace(address(i), Account::empty().with_nonce(i as u64))
+                .unwrap();
+            repository
+                .replace_storage(address(i), U256::from(i), U256::from(i as u64 + 1))
+                .unwrap();
+        }
+
+        repository
+    }
+
+    #[test]
+    fn test_proof_of_existent_account_is_verified() {
+        let repository = repository();
+
+        let proof = repository.proof(&address(7), &[U256::from(7)]);
+
+        assert!(proof.verify(repository.state_root()).is_ok());
+        assert_eq!(7, proof.nonce);
+        assert_eq!(U256::from(8), proof.storage_proof[0].value);
+    }
+
+    #[test]
+    fn test_proof_of_non_existent_account_is_verified_as_empty_account() {
+        let repository = repository();
+
+        let proof = repository.proof(&address(100), &[U256::one()]);
+
+        assert!(proof.verify(repository.state_root()).is_ok());
+        assert_eq!(0, proof.nonce);
+        assert_eq!(EMPTY_ROOT_HASH, proof.storage_hash);
+        assert_eq!(U256::zero(), proof.storage_proof[0].value);
+    }
+
+    #[test]
+    fn test_proof_of_non_existent_slot_is_verified_as_zero() {
+        let repository = repository();
+
+        let proof = repository.proof(&address(7), &[U256::from(100)]);
+
+        assert!(proof.verify(repository.state_root()).is_ok());
+        assert_eq!(U256::zero(), proof.storage_proof[0].value);
+    }
+
+    #[test]
+    fn test_proof_of_account_in_empty_state_is_verified() {
+        let repository = InMemoryEvmStateRepository::default();
+
+        let proof = repository.proof(&address(7), &[U256::one()]);
+
+        assert!(
+            proof.account_proof.is_empty(),
+            "Proof of empty trie has nodes"
+        );
+        assert!(proof.verify(repository.state_root()).is_ok());
+    }
+
+    #[test]
+    fn test_proof_with_tampered_account_is_rejected() {
+        let repository = repository();
+        let mut proof = repository.proof(&address(7), &[]);
+
+        proof.balance = U256::one();
+
+        assert!(
+            matches!(
+                proof.verify(repository.state_root()),
+                Err(Error::InvalidProof(_))
+            ),
+            "Tampered account verified"
+        );
+    }
+
+    #[test]
+    fn test_proof_with_tampered_storage_value_is_rejected() {
+        let repository = repository();
+        let mut proof = repository.proof(&address(7), &[U256::from(7)]);
+
+        proof.storage_proof[0].value = U256::from(9);
+
+        assert!(
+            matches!(
+                proof.verify(repository.state_root()),
+                Err(Error::InvalidProof(_))
+            ),
+            "Tampered storage value verified"
+        );
+    }
+
+    #[test]
+    fn test_proof_against_different_state_root_is_rejected() {
+        let repository = repository();
+        let proof = repository.proof(&address(7), &[]);
+
+        assert!(
+            matches!(proof.verify(B256::ZERO), Err(Error::InvalidProof(_))),
+            "Proof verified against different state root"
+        );
+    }
+
+    #[test]
+    fn test_proof_with_tampered_node_is_rejected() {
+        let repository = repository();
+        let mut proof = repository.proof(&address(7), &[]);
+
+        let last_node = proof.account_proof.last_mut().unwrap();
+        let last_byte = last_node.len() - 1;
+        last_node[last_byte] ^= 1;
+
+        assert!(
+            matches!(
+                proof.verify(repository.state_root()),
+                Err(Error::InvalidProof(_))
+            ),
+            "Proof with tampered node verified"
+        );
+    }
+
+    #[test]
+    fn test_proof_of_slot_with_value_larger_than_32_bytes_is_rejected() {
+        let key = U256::one();
+        let entries = vec![(
+            trie::storage_key(&key),
+            rlp::encode(&vec![1u8; 33]).to_vec(),
+        )];
+        let proof = StorageProof {
+            key,
+            value: U256::zero(),
+            proof: trie::trie_proof(entries.clone(), &trie::storage_key(&key)),
+        };
+
+        assert!(
+            matches!(
+                proof.verify(trie::trie_root(entries)),
+                Err(Error::InvalidProof(_))
+            ),
+            "Oversized storage value verified"
+        );
+    }
+}
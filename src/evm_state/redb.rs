@@ -0,0 +1,422 @@
+//! Persistent, on-disk implementation of [`EvmStateRepository`] backed by [`redb`].
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{
+//!     Account, Address, CacheBuilder, CachedEvmStateRepository, EvmStateRepository,
+//!     RedbEvmStateRepository,
+//! };
+//!
+//! let directory = tempfile::tempdir().unwrap();
+//! let repository = RedbEvmStateRepository::open(directory.path().join("state.redb")).unwrap();
+//! let mut repository =
+//!     CachedEvmStateRepository::new(repository, CacheBuilder::new().with_capacity(10).build_lru());
+//!
+//! repository.replace(Address::ZERO, Account::empty().with_nonce(1)).unwrap();
+//!
+//! assert_eq!(1, repository.get(&Address::ZERO).unwrap().unwrap().nonce());
+//! ```
+use crate::error::{Error, Result};
+use crate::evm_state::codec::trimmed_bytes;
+use crate::evm_state::{
+    check_code_hash, Account, Address, Bytecode, ConcurrentEvmStateRepository, EvmStateRepository,
+    SlimAccount, B256,
+};
+use primitive_types::U256;
+use redb::{Database, TableDefinition, WriteTransaction};
+use std::path::Path;
+
+/// Accounts encoded as [`SlimAccount`] by their addresses.
+const ACCOUNTS: TableDefinition<'static, &[u8], &[u8]> = TableDefinition::new("accounts");
+/// Big-endian values without leading zeros by addresses followed by big-endian slots.
+const STORAGE: TableDefinition<'static, &[u8], &[u8]> = TableDefinition::new("storage");
+/// Contract code by its hash.
+const CODES: TableDefinition<'static, &[u8], &[u8]> = TableDefinition::new("codes");
+
+/// Implements [`EvmStateRepository`] that persists the state in a [`redb`] database file.
+///
+/// Every write is committed in its own durable transaction, so a write that has returned survives
+/// a crash of the process. Slots written with zero are removed rather than stored, as they read as
+/// zero anyway.
+#[derive(Debug)]
+pub struct RedbEvmStateRepository {
+    database: Database,
+}
+
+/// Returns the key of the storage `slot` of an account with the `address`, which keeps the slots
+/// of an account next to each other.
+fn storage_key(address: &Address, slot: &U256) -> [u8; 52] {
+    let mut key = [0u8; 52];
+    key[..20].copy_from_slice(address.as_bytes());
+    slot.to_big_endian(&mut key[20..]);
+    key
+}
+
+fn decode_storage_value(bytes: &[u8]) -> Result<U256> {
+    if bytes.len() > 32 {
+        return Err(Error::backend("storage value is longer than 32 bytes"));
+    }
+    Ok(U256::from_big_endian(bytes))
+}
+
+fn decode_account(bytes: &[u8]) -> Result<Account> {
+    rlp::decode::<SlimAccount>(bytes)
+        .map(|account| account.0)
+        .map_err(Error::backend)
+}
+
+impl RedbEvmStateRepository {
+    /// Opens the database file at the `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(Database::create(path).map_err(Error::backend)?)
+    }
+
+    /// Creates a repository that keeps the state in the `database`, e.g. one created with a custom
+    /// [`redb::Builder`].
+    pub fn new(database: Database) -> Result<Self> {
+        let repository = Self { database };
+        // Tables are created up front, so that reads never miss them
+        repository.write(|transaction| {
+            transaction.open_table(ACCOUNTS).map_err(Error::backend)?;
+            transaction.open_table(STORAGE).map_err(Error::backend)?;
+            transaction.open_table(CODES).map_err(Error::backend)?;
+            Ok(())
+        })?;
+
+        Ok(repository)
+    }
+
+    fn read<T>(
+        &self,
+        table: TableDefinition<'_, &[u8], &[u8]>,
+        key: &[u8],
+        decode: impl FnOnce(&[u8]) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let transaction = self.database.begin_read().map_err(Error::backend)?;
+        let table = transaction.open_table(table).map_err(Error::backend)?;
+        let value = table.get(key).map_err(Error::backend)?;
+
+        value.map(|value| decode(value.value())).transpose()
+    }
+
+    fn write(&self, write: impl FnOnce(&WriteTransaction) -> Result<()>) -> Result<()> {
+        let transaction = self.database.begin_write().map_err(Error::backend)?;
+        // A failed transaction is aborted when dropped
+        write(&transaction)?;
+
+        transaction.commit().map_err(Error::backend)
+    }
+}
+
+impl EvmStateRepository for RedbEvmStateRepository {
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        self.read(ACCOUNTS, address.as_bytes(), decode_account)
+    }
+
+    /// Reads all accounts within a single read transaction, so that they come from the same
+    /// committed state.
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        let transaction = self.database.begin_read().map_err(Error::backend)?;
+        let table = transaction.open_table(ACCOUNTS).map_err(Error::backend)?;
+
+        addresses
+            .iter()
+            .map(|address| {
+                table
+                    .get(address.as_bytes().as_slice())
+                    .map_err(Error::backend)?
+                    .map(|bytes| decode_account(bytes.value()))
+                    .transpose()
+            })
+            .collect()
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        ConcurrentEvmStateRepository::replace(self, address, account)
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_many(self, accounts)
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        ConcurrentEvmStateRepository::delete(self, address)
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        let value = self.read(STORAGE, &storage_key(address, slot), decode_storage_value)?;
+
+        Ok(value.unwrap_or_default())
+    }
+
+    /// Reads the slots by a range of their keys, which start with the address.
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        let transaction = self.database.begin_read().map_err(Error::backend)?;
+        let table = transaction.open_table(STORAGE).map_err(Error::backend)?;
+        let first = storage_key(address, &U256::zero());
+        let last = storage_key(address, &U256::MAX);
+
+        table
+            .range(first.as_slice()..=last.as_slice())
+            .map_err(Error::backend)?
+            .map(|entry| {
+                let (key, value) = entry.map_err(Error::backend)?;
+                let slot = U256::from_big_endian(&key.value()[20..]);
+                Ok((slot, decode_storage_value(value.value())?))
+            })
+            .collect()
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_storage(self, address, slot, value)
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        self.read(CODES, code_hash.as_bytes(), |bytes| {
+            Ok(Bytecode::from(bytes))
+        })
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_code(self, code_hash, code)
+    }
+}
+
+impl ConcurrentEvmStateRepository for RedbEvmStateRepository {
+    fn replace(&self, address: Address, account: Account) -> Result<()> {
+        ConcurrentEvmStateRepository::replace_many(self, vec![(address, account)])
+    }
+
+    /// Writes all accounts within a single transaction, so that either all or none of them are
+    /// persisted.
+    fn replace_many(&self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        self.write(|transaction| {
+            let mut table = transaction.open_table(ACCOUNTS).map_err(Error::backend)?;
+            for (address, account) in accounts {
+                table
+                    .insert(
+                        address.as_bytes().as_slice(),
+                        rlp::encode(&SlimAccount(account)).as_ref(),
+                    )
+                    .map_err(Error::backend)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete(&self, address: &Address) -> Result<()> {
+        self.write(|transaction| {
+            transaction
+                .open_table(ACCOUNTS)
+                .map_err(Error::backend)?
+                .remove(address.as_bytes().as_slice())
+                .map_err(Error::backend)?;
+
+            let first = storage_key(address, &U256::zero());
+            let last = storage_key(address, &U256::MAX);
+            transaction
+                .open_table(STORAGE)
+                .map_err(Error::backend)?
+                .retain_in(first.as_slice()..=last.as_slice(), |_, _| false)
+                .map_err(Error::backend)?;
+            Ok(())
+        })
+    }
+
+    fn replace_storage(&self, address: Address, slot: U256, value: U256) -> Result<()> {
+        self.write(|transaction| {
+            let mut table = transaction.open_table(STORAGE).map_err(Error::backend)?;
+            let key = storage_key(&address, &slot);
+            if value.is_zero() {
+                table.remove(key.as_slice()).map_err(Error::backend)?;
+            } else {
+                table
+                    .insert(key.as_slice(), trimmed_bytes(&value).as_slice())
+                    .map_err(Error::backend)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn replace_code(&self, code_hash: B256, code: Bytecode) -> Result<()> {
+        check_code_hash(&code_hash, &code)?;
+        self.write(|transaction| {
+            let mut table = transaction.open_table(CODES).map_err(Error::backend)?;
+            table
+                .insert(code_hash.as_bytes().as_slice(), code.as_ref())
+                .map_err(Error::backend)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::primitives::keccak256;
+    use crate::evm_state::test_support::address;
+    use crate::evm_state::CachedEvmStateRepository;
+    use crate::CacheBuilder;
+    use std::sync::Arc;
+    use std::thread;
+    use tempfile::TempDir;
+
+    fn repository() -> (TempDir, RedbEvmStateRepository) {
+        let directory = tempfile::tempdir().unwrap();
+        let repository = RedbEvmStateRepository::open(directory.path().join("state.redb")).unwrap();
+
+        (directory, repository)
+    }
+
+    #[test]
+    fn test_replaced_account_is_found() {
+        let (_directory, repository) = repository();
+        let account = Account::empty()
+            .with_nonce(3)
+            .with_balance(U256::exp10(18))
+            .with_code_hash(B256::new([1u8; 32]));
+
+        repository.replace(address(1), account.clone()).unwrap();
+
+        assert_eq!(Some(account), repository.get(&address(1)).unwrap());
+        assert_eq!(None, repository.get(&address(2)).unwrap());
+    }
+
+    #[test]
+    fn test_state_written_before_reopening_is_found() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("state.redb");
+        let code = Bytecode::from(vec![0x60, 0x00]);
+        let code_hash = B256::new(keccak256(&code));
+        {
+            let repository = RedbEvmStateRepository::open(&path).unwrap();
+            repository
+                .replace(address(1), Account::empty().with_nonce(1))
+                .unwrap();
+            repository
+                .replace_storage(address(1), U256::one(), U256::from(7))
+                .unwrap();
+            repository.replace_code(code_hash, code.clone()).unwrap();
+        }
+
+        let repository = RedbEvmStateRepository::open(&path).unwrap();
+
+        assert_eq!(1, repository.get(&address(1)).unwrap().unwrap().nonce());
+        assert_eq!(
+            U256::from(7),
+            repository.get_storage(&address(1), &U256::one()).unwrap()
+        );
+        assert_eq!(Some(code), repository.get_code(&code_hash).unwrap());
+    }
+
+    #[test]
+    fn test_storage_written_with_zero_reads_as_zero() {
+        let (_directory, repository) = repository();
+        repository
+            .replace_storage(address(1), U256::one(), U256::from(7))
+            .unwrap();
+
+        repository
+            .replace_storage(address(1), U256::one(), U256::zero())
+            .unwrap();
+
+        assert_eq!(
+            U256::zero(),
+            repository.get_storage(&address(1), &U256::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deleted_account_is_not_found_together_with_its_storage() {
+        let (_directory, repository) = repository();
+        for address in [address(1), address(2)] {
+            repository.replace(address, Account::empty()).unwrap();
+            repository
+                .replace_storage(address, U256::MAX, U256::one())
+                .unwrap();
+        }
+
+        repository.delete(&address(1)).unwrap();
+
+        assert_eq!(None, repository.get(&address(1)).unwrap());
+        assert_eq!(
+            U256::zero(),
+            repository.get_storage(&address(1), &U256::MAX).unwrap()
+        );
+        assert_eq!(
+            U256::one(),
+            repository.get_storage(&address(2), &U256::MAX).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_account_written_through_cache_is_persisted() {
+        let (_directory, repository) = repository();
+        let repository = Arc::new(repository);
+        let cache = CacheBuilder::new().with_capacity(10).build_lru();
+        let cached_repository = CachedEvmStateRepository::new(repository.clone(), cache);
+
+        cached_repository
+            .replace(address(1), Account::empty().with_nonce(5))
+            .unwrap();
+
+        assert_eq!(5, repository.get(&address(1)).unwrap().unwrap().nonce());
+    }
+
+    #[test]
+    fn test_accounts_written_concurrently_are_found() {
+        let (_directory, repository) = repository();
+        let repository = Arc::new(repository);
+
+        let handles: Vec<_> = (0..4u8)
+            .map(|i| {
+                let repository = repository.clone();
+                thread::spawn(move || {
+                    ConcurrentEvmStateRepository::replace(
+                        repository.as_ref(),
+                        address(i),
+                        Account::empty().with_nonce(i as u64),
+                    )
+                    .unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for i in 0..4u8 {
+            assert_eq!(
+                i as u64,
+                repository.get(&address(i)).unwrap().unwrap().nonce()
+            );
+        }
+    }
+
+    #[test]
+    fn test_accounts_replaced_in_batch_are_found_in_batch() {
+        let (_directory, repository) = repository();
+
+        ConcurrentEvmStateRepository::replace_many(
+            &repository,
+            vec![
+                (address(1), Account::empty().with_nonce(1)),
+                (address(2), Account::empty().with_nonce(2)),
+            ],
+        )
+        .unwrap();
+
+        let actual_accounts = repository
+            .get_many(&[address(2), address(3), address(1)])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                Some(Account::empty().with_nonce(2)),
+                None,
+                Some(Account::empty().with_nonce(1)),
+            ],
+            actual_accounts
+        );
+    }
+}
@@ -1,34 +1,91 @@
-use crate::evm_state::{Account, Address, EvmStateRepository};
+use crate::error::{Error, Result};
+use crate::evm_state::{check_code_hash, Account, Address, Bytecode, EvmStateRepository, B256};
 use primitive_types::U256;
-use revm::primitives::AccountInfo;
+use revm::primitives::{AccountInfo, AccountStatus, Bytes, StorageSlot};
 use revm::{DatabaseCommit, DatabaseRef};
 use std::collections::HashMap;
 
 /// Implements [`EvmStateRepository`] that accesses a [`DatabaseRef`] used by [`revm`].
+///
+/// A [`DatabaseCommit`] can only store contract code as a part of an account. Therefore, code
+/// written by [`EvmStateRepository::replace_code`] is kept aside until an account with the
+/// corresponding code hash is written, and only then it reaches the database. Until that, the
+/// code is found by [`EvmStateRepository::get_code`] of this repository only and
+/// [`EvmStateRepository::flush`] cannot persist it, since there is no account to attach it to.
+///
+/// Likewise, committing storage creates the account it belongs to. Storage written by
+/// [`EvmStateRepository::replace_storage`] to an account that does not exist is therefore kept
+/// aside until the account is written, so that the account is not found before that.
+///
+/// A [`DatabaseRef`] only looks storage slots up, so [`EvmStateRepository::get_all_storage`] fails
+/// with [`Error::Unsupported`].
 #[derive(Debug, Clone, PartialEq)]
 pub struct RevmStateRepository<D: DatabaseRef + DatabaseCommit> {
     database: D,
+    codes: HashMap<B256, Bytecode>,
+    storage: HashMap<Address, HashMap<U256, U256>>,
 }
 
+/// Converts a [`U256`] into the [`revm`] representation, which shares its little-endian limb
+/// order.
+pub(crate) fn to_revm_u256(value: U256) -> revm::primitives::U256 {
+    revm::primitives::U256::from_limbs(value.0)
+}
+
+pub(crate) fn from_revm_u256(value: revm::primitives::U256) -> U256 {
+    U256(value.into_limbs())
+}
+
+impl From<revm::primitives::Address> for Address {
+    fn from(value: revm::primitives::Address) -> Self {
+        Self::new(value.into_array())
+    }
+}
+
+impl From<Address> for revm::primitives::Address {
+    fn from(value: Address) -> Self {
+        Self::new(value.into())
+    }
+}
+
+impl From<revm::primitives::B256> for B256 {
+    fn from(value: revm::primitives::B256) -> Self {
+        Self::new(value.0)
+    }
+}
+
+impl From<B256> for revm::primitives::B256 {
+    fn from(value: B256) -> Self {
+        Self::new(value.into())
+    }
+}
+
+/// Converts an [`AccountInfo`] read from [`revm`] into an [`Account`].
+///
+/// [`revm`] does not track storage roots, so the storage root of the resulting account is zero.
+/// Contract code carried by the [`AccountInfo`] is not a part of an [`Account`] and is only
+/// reachable through its code hash.
 impl From<AccountInfo> for Account {
     fn from(value: AccountInfo) -> Self {
         Self {
             nonce: value.nonce,
-            balance: U256::from_little_endian(value.balance.as_le_slice()),
-            code_hash: U256::from_little_endian(value.code_hash.as_slice()),
-            storage_root: Default::default(),
+            balance: from_revm_u256(value.balance),
+            code_hash: value.code_hash.into(),
+            storage_root: B256::ZERO,
         }
     }
 }
 
+/// Converts an [`Account`] into an [`AccountInfo`] written to [`revm`].
+///
+/// The storage root is dropped since [`revm`] does not track it, and no code is attached. A code
+/// stored by [`RevmStateRepository`] is attached when the account is written.
 impl From<Account> for AccountInfo {
     fn from(value: Account) -> Self {
         Self {
             nonce: value.nonce,
-            balance: revm::primitives::U256::from_limbs_slice(&value.balance.0[..]),
-            code_hash: revm::primitives::B256::from_slice(
-                value.code_hash.0.map(|v| v.to_le_bytes()).as_flattened(),
-            ),
+            balance: to_revm_u256(value.balance),
+            code_hash: value.code_hash.into(),
             code: None,
         }
     }
@@ -39,60 +96,293 @@ impl From<Account> for revm::primitives::Account {
         Self {
             info: value.into(),
             storage: Default::default(),
-            status: revm::primitives::state::AccountStatus::Touched,
+            status: AccountStatus::Touched,
         }
     }
 }
 
-impl<D: DatabaseRef + DatabaseCommit> EvmStateRepository for RevmStateRepository<D> {
-    fn get(&self, address: &Address) -> Option<Account> {
+impl<D> EvmStateRepository for RevmStateRepository<D>
+where
+    D: DatabaseRef + DatabaseCommit,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        Ok(self
+            .database
+            .basic_ref((*address).into())
+            .map_err(Error::backend)?
+            .map(Into::into))
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        self.replace_many(vec![(address, account)])
+    }
+
+    /// Writes all `accounts` by a single [`DatabaseCommit::commit`].
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        let accounts = accounts
+            .into_iter()
+            .map(|(address, account)| {
+                let account = self.revm_account(&address, account)?;
+                Ok((address.into(), account))
+            })
+            .collect::<Result<_>>()?;
+
+        self.database.commit(accounts);
+
+        Ok(())
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        self.storage.remove(address);
+
+        let account = revm::primitives::Account {
+            info: AccountInfo::default(),
+            storage: Default::default(),
+            status: AccountStatus::SelfDestructed | AccountStatus::Touched,
+        };
+
+        self.database.commit({
+            let mut map = HashMap::new();
+            map.insert((*address).into(), account);
+            map
+        });
+
+        Ok(())
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        if let Some(value) = self
+            .storage
+            .get(address)
+            .and_then(|storage| storage.get(slot))
+        {
+            return Ok(*value);
+        }
+
         self.database
-            .basic_ref(revm::primitives::Address::from(address))
-            .ok()
-            .flatten()
-            .map(Into::into)
+            .storage_ref((*address).into(), to_revm_u256(*slot))
+            .map(from_revm_u256)
+            .map_err(Error::backend)
+    }
+
+    fn get_all_storage(&self, _address: &Address) -> Result<Vec<(U256, U256)>> {
+        Err(Error::unsupported(
+            "revm databases cannot enumerate storage slots",
+        ))
     }
 
-    fn replace(&mut self, address: Address, account: Account) {
+    /// Commits the `value` together with the account it belongs to, or keeps it aside until the
+    /// account is written if the account does not exist.
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        let info = match self
+            .database
+            .basic_ref(address.into())
+            .map_err(Error::backend)?
+        {
+            Some(info) => info,
+            None => {
+                self.storage.entry(address).or_default().insert(slot, value);
+                return Ok(());
+            }
+        };
+        let address = revm::primitives::Address::from(address);
+        let slot = to_revm_u256(slot);
+        let original_value = self
+            .database
+            .storage_ref(address, slot)
+            .map_err(Error::backend)?;
+        let account = revm::primitives::Account {
+            info,
+            storage: {
+                let mut map = HashMap::new();
+                map.insert(
+                    slot,
+                    StorageSlot::new_changed(original_value, to_revm_u256(value)),
+                );
+                map
+            },
+            status: AccountStatus::Touched,
+        };
+
         self.database.commit({
             let mut map = HashMap::new();
-            map.insert(address.into(), account.into());
+            map.insert(address, account);
             map
         });
+
+        Ok(())
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        if let Some(code) = self.codes.get(code_hash) {
+            return Ok(Some(code.clone()));
+        }
+
+        let code = self
+            .database
+            .code_by_hash_ref((*code_hash).into())
+            .map_err(Error::backend)?;
+
+        // Unknown code is reported by the database as empty bytecode
+        if code.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Bytecode::from(code.original_bytes().as_ref())))
+    }
+
+    /// Keeps the `code` aside until an account with the `code_hash` is written.
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        check_code_hash(&code_hash, &code)?;
+        self.codes.insert(code_hash, code);
+
+        Ok(())
     }
 }
 
 impl<D: DatabaseRef + DatabaseCommit> RevmStateRepository<D> {
     pub fn new(database: D) -> Self {
-        Self { database }
+        Self {
+            database,
+            codes: HashMap::new(),
+            storage: HashMap::new(),
+        }
+    }
+}
+
+impl<D> RevmStateRepository<D>
+where
+    D: DatabaseRef + DatabaseCommit,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    /// Converts the `account` at the `address` into the [`revm`] representation with its code
+    /// and storage attached, if they were kept aside.
+    fn revm_account(
+        &mut self,
+        address: &Address,
+        account: Account,
+    ) -> Result<revm::primitives::Account> {
+        let code = self.codes.remove(&account.code_hash);
+        let mut account: revm::primitives::Account = account.into();
+        account.info.code =
+            code.map(|code| revm::primitives::Bytecode::new_raw(Bytes::copy_from_slice(&code)));
+
+        for (slot, value) in self.storage.remove(address).unwrap_or_default() {
+            let slot = to_revm_u256(slot);
+            let original_value = self
+                .database
+                .storage_ref((*address).into(), slot)
+                .map_err(Error::backend)?;
+            account.storage.insert(
+                slot,
+                StorageSlot::new_changed(original_value, to_revm_u256(value)),
+            );
+        }
+
+        Ok(account)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use primitive_types::H160;
+    use crate::evm_state::primitives::keccak256;
+    use crate::evm_state::EMPTY_CODE_HASH;
+    use proptest::prelude::*;
     use revm::InMemoryDB;
 
+    fn any_u256() -> impl Strategy<Value = U256> {
+        any::<[u64; 4]>().prop_map(U256)
+    }
+
+    fn any_account() -> impl Strategy<Value = Account> {
+        (any::<u64>(), any_u256(), any_u256()).prop_map(|(nonce, balance, code_hash)| {
+            Account::new(nonce, balance, B256::from(code_hash), B256::ZERO)
+        })
+    }
+
     #[test]
-    fn test_account_by_existent_address_from_repository_is_found() {
-        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+    fn test_address_and_hash_converted_to_revm_and_back_are_unchanged() {
+        let address: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+            .parse()
+            .unwrap();
+        let hash = EMPTY_CODE_HASH;
+
+        let revm_address = revm::primitives::Address::from(address);
+        let revm_hash = revm::primitives::B256::from(hash);
+
+        assert_eq!(address.as_bytes(), revm_address.as_slice());
+        assert_eq!(hash.as_bytes(), revm_hash.as_slice());
+        assert_eq!(address, Address::from(revm_address));
+        assert_eq!(hash, B256::from(revm_hash));
+    }
+
+    proptest! {
+        #[test]
+        fn test_account_converted_to_account_info_and_back_is_unchanged(account in any_account()) {
+            let account_info = AccountInfo::from(account.clone());
+
+            prop_assert_eq!(account, Account::from(account_info));
+        }
+
+        #[test]
+        fn test_account_info_converted_to_account_and_back_is_unchanged(
+            nonce in any::<u64>(),
+            balance in any::<[u8; 32]>(),
+            code_hash in any::<[u8; 32]>(),
+        ) {
+            let account_info = AccountInfo {
+                nonce,
+                balance: revm::primitives::U256::from_be_bytes(balance),
+                code_hash: revm::primitives::B256::from(code_hash),
+                code: None,
+            };
+
+            prop_assert_eq!(account_info.clone(), AccountInfo::from(Account::from(account_info)));
+        }
+
+        #[test]
+        fn test_code_hash_is_converted_as_big_endian_bytes(code_hash in any_u256()) {
+            let account = Account::new(0, U256::zero(), B256::from(code_hash), B256::ZERO);
+
+            let account_info = AccountInfo::from(account);
+
+            prop_assert_eq!((code_hash >> 248).low_u32() as u8, account_info.code_hash[0]);
+            prop_assert_eq!(code_hash.low_u32() as u8, account_info.code_hash[31]);
+        }
+    }
 
-        repository.replace(
-            Address::from(H160::zero()),
-            Account::new(0, U256::zero(), U256::zero(), U256::zero()),
+    #[test]
+    fn test_storage_root_is_not_kept_by_account_info() {
+        let account = Account::new(
+            1,
+            U256::from(2),
+            B256::from(U256::from(3)),
+            B256::from(U256::from(4)),
         );
 
-        let actual_account = repository.get(&Address::from(H160::zero()));
-        let expected_account = Account::new(
-            0,
-            U256::zero(),
-            U256::from_dec_str(
-                "50949722399999162638671808714117529995988557342659710791111524410371378434757",
-            )
-            .unwrap(),
-            U256::zero(),
+        let actual_account = Account::from(AccountInfo::from(account));
+
+        assert_eq!(
+            Account::new(1, U256::from(2), B256::from(U256::from(3)), B256::ZERO),
+            actual_account
         );
+    }
+
+    #[test]
+    fn test_account_by_existent_address_from_repository_is_found() {
+        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+
+        repository
+            .replace(
+                Address::ZERO,
+                Account::new(0, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
+
+        let actual_account = repository.get(&Address::ZERO).unwrap();
+        let expected_account = Account::new(0, U256::zero(), EMPTY_CODE_HASH, B256::ZERO);
 
         assert!(actual_account.is_some(), "Account not found");
 
@@ -101,15 +391,169 @@ mod tests {
         assert_eq!(expected_account, actual_account);
     }
 
+    #[test]
+    fn test_accounts_replaced_in_batch_are_found_in_batch() {
+        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+        let code = Bytecode::from(vec![0x60, 0x00]);
+        let code_hash = B256::new(keccak256(&code));
+        repository.replace_code(code_hash, code.clone()).unwrap();
+
+        repository
+            .replace_many(vec![
+                (Address::ZERO, Account::empty().with_nonce(1)),
+                (
+                    Address::new([1u8; 20]),
+                    Account::empty().with_nonce(2).with_code_hash(code_hash),
+                ),
+            ])
+            .unwrap();
+
+        let actual_accounts = repository
+            .get_many(&[
+                Address::ZERO,
+                Address::new([2u8; 20]),
+                Address::new([1u8; 20]),
+            ])
+            .unwrap();
+
+        assert_eq!(1, actual_accounts[0].as_ref().unwrap().nonce());
+        assert_eq!(None, actual_accounts[1]);
+        assert_eq!(2, actual_accounts[2].as_ref().unwrap().nonce());
+        assert_eq!(Some(code), repository.get_code(&code_hash).unwrap());
+    }
+
     #[test]
     fn test_account_by_non_existent_address_from_repository_is_not_found() {
         let repository = RevmStateRepository::new(InMemoryDB::default());
 
-        let actual_account = repository.get(&Address::from(H160::zero()));
+        let actual_account = repository.get(&Address::ZERO).unwrap();
 
         assert!(
             actual_account.is_none(),
             "Account found but none was present"
         );
     }
+
+    #[test]
+    fn test_storage_slot_written_to_repository_is_read_back() {
+        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+
+        let actual_value = repository
+            .get_storage(&Address::ZERO, &U256::one())
+            .unwrap();
+
+        assert_eq!(U256::from(7), actual_value);
+    }
+
+    #[test]
+    fn test_storage_written_before_account_reaches_database_with_account() {
+        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+        repository
+            .replace(Address::ZERO, Account::empty().with_nonce(1))
+            .unwrap();
+
+        let actual_value = repository
+            .database
+            .storage_ref(Address::ZERO.into(), to_revm_u256(U256::one()))
+            .unwrap();
+
+        assert_eq!(U256::from(7), from_revm_u256(actual_value));
+        assert!(repository.storage.is_empty(), "Storage is still kept aside");
+    }
+
+    #[test]
+    fn test_all_storage_of_account_is_not_supported() {
+        let repository = RevmStateRepository::new(InMemoryDB::default());
+
+        let actual_storage = repository.get_all_storage(&Address::ZERO);
+
+        assert!(
+            matches!(actual_storage, Err(Error::Unsupported(_))),
+            "Storage enumerated"
+        );
+    }
+
+    #[test]
+    fn test_storage_slot_never_written_to_repository_reads_as_zero() {
+        let repository = RevmStateRepository::new(InMemoryDB::default());
+
+        let actual_value = repository
+            .get_storage(&Address::ZERO, &U256::one())
+            .unwrap();
+
+        assert_eq!(U256::zero(), actual_value);
+    }
+
+    #[test]
+    fn test_code_of_written_account_is_found_by_its_hash() {
+        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+        let code_hash = B256::new(keccak256([0x60, 0x00]));
+
+        repository
+            .replace_code(code_hash, Bytecode::from(vec![0x60, 0x00]))
+            .unwrap();
+        repository
+            .replace(
+                Address::ZERO,
+                Account::new(0, U256::zero(), code_hash, B256::ZERO),
+            )
+            .unwrap();
+
+        let actual_code = repository.get_code(&code_hash).unwrap();
+
+        assert_eq!(Some(Bytecode::from(vec![0x60, 0x00])), actual_code);
+    }
+
+    #[test]
+    fn test_code_with_mismatching_hash_is_rejected() {
+        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+
+        let result = repository.replace_code(B256::from(U256::one()), Bytecode::from(vec![0x60]));
+
+        assert!(
+            matches!(result, Err(Error::InvalidInput(_))),
+            "Mismatching code hash accepted"
+        );
+    }
+
+    #[test]
+    fn test_code_by_non_existent_hash_from_repository_is_not_found() {
+        let repository = RevmStateRepository::new(InMemoryDB::default());
+
+        let actual_code = repository.get_code(&B256::from(U256::one())).unwrap();
+
+        assert!(actual_code.is_none(), "Code found but none was present");
+    }
+
+    #[test]
+    fn test_deleted_account_is_not_found_and_its_storage_is_cleared() {
+        let mut repository = RevmStateRepository::new(InMemoryDB::default());
+        repository
+            .replace(
+                Address::ZERO,
+                Account::new(0, U256::zero(), B256::ZERO, B256::ZERO),
+            )
+            .unwrap();
+        repository
+            .replace_storage(Address::ZERO, U256::one(), U256::from(7))
+            .unwrap();
+
+        repository.delete(&Address::ZERO).unwrap();
+
+        let actual_account = repository.get(&Address::ZERO).unwrap();
+        let actual_value = repository
+            .get_storage(&Address::ZERO, &U256::one())
+            .unwrap();
+
+        assert!(actual_account.is_none(), "Deleted account found");
+        assert_eq!(U256::zero(), actual_value);
+    }
 }
@@ -0,0 +1,404 @@
+//! Execution of [`revm`] transactions directly against an [`EvmStateRepository`].
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{
+//!     Account, Address, CacheBuilder, CachedEvmStateRepository, EvmStateRepository,
+//!     InMemoryEvmStateRepository, RevmDatabase,
+//! };
+//! use primitive_types::U256;
+//! use revm::primitives::TransactTo;
+//! use revm::Evm;
+//!
+//! let sender = Address::new([1u8; 20]);
+//! let recipient = Address::new([2u8; 20]);
+//! let mut repository = CachedEvmStateRepository::new(
+//!     InMemoryEvmStateRepository::default(),
+//!     CacheBuilder::new().with_capacity(10).build_lru(),
+//! );
+//! repository.replace(sender, Account::empty().with_balance(U256::from(10))).unwrap();
+//!
+//! let mut evm = Evm::builder()
+//!     .with_db(RevmDatabase::new(repository))
+//!     .modify_tx_env(|tx| {
+//!         tx.caller = sender.into();
+//!         tx.transact_to = TransactTo::Call(recipient.into());
+//!         tx.value = revm::primitives::U256::from(3);
+//!     })
+//!     .build();
+//! evm.transact_commit().unwrap();
+//!
+//! let repository = evm.into_context().evm.inner.db.into_inner();
+//! assert_eq!(U256::from(3), repository.get(&recipient).unwrap().unwrap().balance());
+//! ```
+use crate::error::{Error, Result};
+use crate::evm_state::revm::{from_revm_u256, to_revm_u256};
+use crate::evm_state::{
+    Account, Address, Bytecode, EvmStateRepository, B256, EMPTY_CODE_HASH, EMPTY_ROOT_HASH,
+};
+use revm::primitives::{AccountInfo, Bytes};
+use revm::{Database, DatabaseCommit, DatabaseRef};
+use std::collections::HashMap;
+
+/// Implements [`DatabaseRef`], [`Database`] and [`DatabaseCommit`] of [`revm`] on top of an
+/// [`EvmStateRepository`], so that transactions are executed directly against the repository, e.g.
+/// a [`CachedEvmStateRepository`](crate::CachedEvmStateRepository) that keeps the hot state cached.
+///
+/// [`revm`] does not track storage roots, so an account whose storage was changed is written with a
+/// zero storage root, which marks the root as stale until it is recomputed, e.g. by
+/// [`EvmStateSnapshot::state_root`](crate::EvmStateSnapshot::state_root). Accounts whose storage
+/// was not changed keep their storage root. The repository does not hold block hashes, so they are provided by
+/// [`RevmDatabase::with_block_hashes`] and hashes of other blocks are zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevmDatabase<R: EvmStateRepository> {
+    repository: R,
+    block_hashes: HashMap<u64, B256>,
+}
+
+impl<R: EvmStateRepository> RevmDatabase<R> {
+    pub fn new(repository: R) -> Self {
+        Self {
+            repository,
+            block_hashes: HashMap::new(),
+        }
+    }
+
+    /// Sets hashes of blocks by their numbers, which are looked up by the `BLOCKHASH` opcode.
+    pub fn with_block_hashes(
+        mut self,
+        block_hashes: impl IntoIterator<Item = (u64, B256)>,
+    ) -> Self {
+        self.block_hashes.extend(block_hashes);
+        self
+    }
+
+    /// Returns the underlying repository.
+    pub fn into_inner(self) -> R {
+        self.repository
+    }
+
+    /// Writes `changes` made by a transaction into the repository like
+    /// [`DatabaseCommit::commit`], but reports a failure of the repository instead of panicking.
+    ///
+    /// Self-destructed accounts are deleted together with their storage, and so is the previous
+    /// storage of newly created accounts. Accounts that were not touched are skipped. The storage
+    /// root of an account is kept unless its storage was changed.
+    pub fn try_commit(
+        &mut self,
+        changes: HashMap<revm::primitives::Address, revm::primitives::Account>,
+    ) -> Result<()> {
+        let mut accounts = Vec::with_capacity(changes.len());
+
+        for (address, account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+
+            let address = Address::from(address);
+            if account.is_selfdestructed() {
+                self.repository.delete(&address)?;
+                continue;
+            }
+            let storage_changed = account.storage.values().any(|value| value.is_changed());
+            let storage_root = if storage_changed {
+                B256::ZERO
+            } else if account.is_created() {
+                EMPTY_ROOT_HASH
+            } else {
+                self.repository
+                    .get(&address)?
+                    .map_or(B256::ZERO, |account| account.storage_root())
+            };
+            if account.is_created() {
+                self.repository.delete(&address)?;
+            }
+
+            if let Some(code) = account.info.code.as_ref().filter(|code| !code.is_empty()) {
+                self.repository.replace_code(
+                    account.info.code_hash.into(),
+                    Bytecode::from(code.original_bytes().as_ref()),
+                )?;
+            }
+            for (slot, value) in &account.storage {
+                if value.is_changed() {
+                    self.repository.replace_storage(
+                        address,
+                        from_revm_u256(*slot),
+                        from_revm_u256(value.present_value()),
+                    )?;
+                }
+            }
+
+            accounts.push((
+                address,
+                Account::from(account.info).with_storage_root(storage_root),
+            ));
+        }
+
+        self.repository.replace_many(accounts)
+    }
+}
+
+impl<R: EvmStateRepository> DatabaseRef for RevmDatabase<R> {
+    type Error = Error;
+
+    fn basic_ref(&self, address: revm::primitives::Address) -> Result<Option<AccountInfo>> {
+        Ok(self.repository.get(&address.into())?.map(|account| {
+            // A zero code hash stands for no code as well, which revm only recognizes by its hash
+            let code_hash = match account.code_hash() {
+                B256::ZERO => EMPTY_CODE_HASH,
+                code_hash => code_hash,
+            };
+            account.with_code_hash(code_hash).into()
+        }))
+    }
+
+    fn code_by_hash_ref(
+        &self,
+        code_hash: revm::primitives::B256,
+    ) -> Result<revm::primitives::Bytecode> {
+        // Unknown code is reported as empty bytecode, like revm databases do
+        Ok(self
+            .repository
+            .get_code(&code_hash.into())?
+            .map(|code| revm::primitives::Bytecode::new_raw(Bytes::copy_from_slice(&code)))
+            .unwrap_or_default())
+    }
+
+    fn storage_ref(
+        &self,
+        address: revm::primitives::Address,
+        index: revm::primitives::U256,
+    ) -> Result<revm::primitives::U256> {
+        self.repository
+            .get_storage(&address.into(), &from_revm_u256(index))
+            .map(to_revm_u256)
+    }
+
+    fn block_hash_ref(&self, number: revm::primitives::U256) -> Result<revm::primitives::B256> {
+        let block_hash = u64::try_from(number)
+            .ok()
+            .and_then(|number| self.block_hashes.get(&number))
+            .copied()
+            .unwrap_or(B256::ZERO);
+
+        Ok(block_hash.into())
+    }
+}
+
+impl<R: EvmStateRepository> Database for RevmDatabase<R> {
+    type Error = Error;
+
+    fn basic(&mut self, address: revm::primitives::Address) -> Result<Option<AccountInfo>> {
+        self.basic_ref(address)
+    }
+
+    fn code_by_hash(
+        &mut self,
+        code_hash: revm::primitives::B256,
+    ) -> Result<revm::primitives::Bytecode> {
+        self.code_by_hash_ref(code_hash)
+    }
+
+    fn storage(
+        &mut self,
+        address: revm::primitives::Address,
+        index: revm::primitives::U256,
+    ) -> Result<revm::primitives::U256> {
+        self.storage_ref(address, index)
+    }
+
+    fn block_hash(&mut self, number: revm::primitives::U256) -> Result<revm::primitives::B256> {
+        self.block_hash_ref(number)
+    }
+}
+
+impl<R: EvmStateRepository> DatabaseCommit for RevmDatabase<R> {
+    /// Writes `changes` made by a transaction into the repository.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the repository fails to write, as [`DatabaseCommit`] cannot report failures.
+    /// Use [`RevmDatabase::try_commit`] to handle them.
+    fn commit(&mut self, changes: HashMap<revm::primitives::Address, revm::primitives::Account>) {
+        if let Err(error) = self.try_commit(changes) {
+            panic!("Failed to commit changes to the repository: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::primitives::keccak256;
+    use crate::evm_state::test_support::address;
+    use crate::evm_state::{
+        CachedEvmStateRepository, ConcurrentEvmStateRepository,
+        ConcurrentInMemoryEvmStateRepository,
+    };
+    use crate::CacheBuilder;
+    use primitive_types::U256;
+    use revm::primitives::TransactTo;
+    use revm::Evm;
+    use std::sync::Arc;
+
+    fn transact(
+        database: RevmDatabase<impl EvmStateRepository>,
+        caller: Address,
+        to: Address,
+        value: U256,
+    ) {
+        let mut evm = Evm::builder()
+            .with_db(database)
+            .modify_tx_env(|tx| {
+                tx.caller = caller.into();
+                tx.transact_to = TransactTo::Call(to.into());
+                tx.value = to_revm_u256(value);
+            })
+            .build();
+
+        evm.transact_commit().unwrap();
+    }
+
+    #[test]
+    fn test_value_transferred_by_transaction_is_written_to_cached_repository() {
+        let inner = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        let repository = CachedEvmStateRepository::new(
+            inner.clone(),
+            CacheBuilder::new().with_capacity(10).build_lru(),
+        );
+        repository
+            .replace(address(0x41), Account::empty().with_balance(U256::from(10)))
+            .unwrap();
+
+        transact(
+            RevmDatabase::new(repository),
+            address(0x41),
+            address(0x42),
+            U256::from(3),
+        );
+
+        let sender = inner.get(&address(0x41)).unwrap().unwrap();
+        let recipient = inner.get(&address(0x42)).unwrap().unwrap();
+        assert_eq!(U256::from(7), sender.balance());
+        assert_eq!(1, sender.nonce());
+        assert_eq!(U256::from(3), recipient.balance());
+    }
+
+    #[test]
+    fn test_storage_written_by_contract_is_written_to_repository() {
+        let repository = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        // PUSH1 7 PUSH1 1 SSTORE STOP
+        let code = Bytecode::from(vec![0x60, 0x07, 0x60, 0x01, 0x55, 0x00]);
+        let code_hash = B256::new(keccak256(&code));
+        ConcurrentEvmStateRepository::replace_code(repository.as_ref(), code_hash, code).unwrap();
+        ConcurrentEvmStateRepository::replace(
+            repository.as_ref(),
+            address(0x42),
+            Account::empty().with_code_hash(code_hash),
+        )
+        .unwrap();
+
+        transact(
+            RevmDatabase::new(repository.clone()),
+            address(0x41),
+            address(0x42),
+            U256::zero(),
+        );
+
+        assert_eq!(
+            U256::from(7),
+            repository
+                .get_storage(&address(0x42), &U256::one())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_storage_root_is_kept_unless_storage_is_changed() {
+        let repository = Arc::new(ConcurrentInMemoryEvmStateRepository::default());
+        // PUSH1 7 PUSH1 1 SSTORE STOP
+        let code = Bytecode::from(vec![0x60, 0x07, 0x60, 0x01, 0x55, 0x00]);
+        let code_hash = B256::new(keccak256(&code));
+        let storage_root = B256::new([1u8; 32]);
+        ConcurrentEvmStateRepository::replace_code(repository.as_ref(), code_hash, code).unwrap();
+        ConcurrentEvmStateRepository::replace_many(
+            repository.as_ref(),
+            vec![
+                (
+                    address(0x41),
+                    Account::empty()
+                        .with_balance(U256::from(10))
+                        .with_storage_root(storage_root),
+                ),
+                (
+                    address(0x42),
+                    Account::empty()
+                        .with_code_hash(code_hash)
+                        .with_storage_root(storage_root),
+                ),
+            ],
+        )
+        .unwrap();
+
+        transact(
+            RevmDatabase::new(repository.clone()),
+            address(0x41),
+            address(0x42),
+            U256::from(3),
+        );
+
+        let sender = repository.get(&address(0x41)).unwrap().unwrap();
+        let contract = repository.get(&address(0x42)).unwrap().unwrap();
+        assert_eq!(U256::from(7), sender.balance());
+        assert_eq!(storage_root, sender.storage_root());
+        assert_eq!(U256::from(3), contract.balance());
+        assert_eq!(B256::ZERO, contract.storage_root());
+    }
+
+    #[test]
+    fn test_account_read_through_database_has_empty_code_hash_instead_of_zero() {
+        let repository = ConcurrentInMemoryEvmStateRepository::default();
+        repository
+            .replace(address(1), Account::empty().with_code_hash(B256::ZERO))
+            .unwrap();
+        let database = RevmDatabase::new(repository);
+
+        let actual_account = database.basic_ref(address(1).into()).unwrap().unwrap();
+
+        assert_eq!(
+            revm::primitives::B256::from(EMPTY_CODE_HASH),
+            actual_account.code_hash
+        );
+    }
+
+    #[test]
+    fn test_block_hash_is_looked_up_by_block_number() {
+        let database = RevmDatabase::new(ConcurrentInMemoryEvmStateRepository::default())
+            .with_block_hashes([(7, B256::new([7u8; 32]))]);
+
+        let known_hash = database
+            .block_hash_ref(revm::primitives::U256::from(7))
+            .unwrap();
+        let unknown_hash = database
+            .block_hash_ref(revm::primitives::U256::from(8))
+            .unwrap();
+
+        assert_eq!(
+            revm::primitives::B256::from(B256::new([7u8; 32])),
+            known_hash
+        );
+        assert_eq!(revm::primitives::B256::ZERO, unknown_hash);
+    }
+
+    #[test]
+    fn test_unknown_code_is_read_as_empty_bytecode() {
+        let database = RevmDatabase::new(ConcurrentInMemoryEvmStateRepository::default());
+
+        let actual_code = database
+            .code_by_hash_ref(revm::primitives::B256::from(B256::new([1u8; 32])))
+            .unwrap();
+
+        assert!(actual_code.is_empty(), "Unknown code is not empty");
+    }
+}
@@ -0,0 +1,327 @@
+//! (De)serialization of EVM state entities using the Ethereum JSON conventions.
+//!
+//! Numbers are encoded as [hex quantities] without leading zeros, such as `"0x1"`, while
+//! addresses, hashes and code are encoded as 0x-prefixed [unformatted data].
+//!
+//! [hex quantities]: https://ethereum.org/en/developers/docs/apis/json-rpc/#quantities-encoding
+//! [unformatted data]: https://ethereum.org/en/developers/docs/apis/json-rpc/#unformatted-data-encoding
+use crate::evm_state::{Address, Bytecode, B256};
+use primitive_types::U256;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::LowerHex;
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{self:#x}"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for B256 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{self:#x}"))
+    }
+}
+
+impl<'de> Deserialize<'de> for B256 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// A number encoded as a hex quantity.
+pub(crate) trait Quantity: Sized + LowerHex {
+    fn from_hex(digits: &str) -> Option<Self>;
+
+    fn to_quantity(&self) -> String {
+        format!("{self:#x}")
+    }
+
+    fn from_quantity(value: &str) -> Result<Self, String> {
+        value
+            .strip_prefix("0x")
+            .filter(|digits| !digits.is_empty())
+            .and_then(Self::from_hex)
+            .ok_or_else(|| format!("invalid hex quantity: {value}"))
+    }
+}
+
+impl Quantity for u64 {
+    fn from_hex(digits: &str) -> Option<Self> {
+        u64::from_str_radix(digits, 16).ok()
+    }
+}
+
+impl Quantity for U256 {
+    fn from_hex(digits: &str) -> Option<Self> {
+        U256::from_str_radix(digits, 16).ok()
+    }
+}
+
+fn to_data(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn from_data(value: &str) -> Result<Vec<u8>, String> {
+    value
+        .strip_prefix("0x")
+        .and_then(|digits| hex::decode(digits).ok())
+        .ok_or_else(|| format!("invalid hex data: {value}"))
+}
+
+/// Serializes a number as a hex quantity.
+pub(crate) mod quantity {
+    use super::*;
+
+    pub(crate) fn serialize<T: Quantity, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_quantity())
+    }
+
+    pub(crate) fn deserialize<'de, T: Quantity, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        T::from_quantity(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// Serializes a list of byte strings as hex data.
+pub(crate) mod data_list {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        list: &[Vec<u8>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        list.iter()
+            .map(|bytes| to_data(bytes))
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|data| from_data(data))
+            .collect::<Result<_, String>>()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Serializes contract storage of accounts as maps of hex quantities.
+pub(crate) mod storage {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        storage: &BTreeMap<Address, BTreeMap<U256, U256>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        storage
+            .iter()
+            .map(|(address, slots)| {
+                let slots: BTreeMap<String, String> = slots
+                    .iter()
+                    .map(|(slot, value)| (slot.to_quantity(), value.to_quantity()))
+                    .collect();
+                (address, slots)
+            })
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Address, BTreeMap<U256, U256>>, D::Error> {
+        BTreeMap::<Address, BTreeMap<String, String>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(address, slots)| {
+                let slots = slots
+                    .iter()
+                    .map(|(slot, value)| {
+                        Ok((U256::from_quantity(slot)?, U256::from_quantity(value)?))
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok((address, slots))
+            })
+            .collect::<Result<_, String>>()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Serializes changes of contract storage of accounts as maps keyed by hex quantities.
+pub(crate) mod storage_changes {
+    use super::*;
+    use crate::evm_state::StorageChange;
+
+    pub(crate) fn serialize<S: Serializer>(
+        storage: &BTreeMap<Address, BTreeMap<U256, StorageChange>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        storage
+            .iter()
+            .map(|(address, slots)| {
+                let slots: BTreeMap<String, &StorageChange> = slots
+                    .iter()
+                    .map(|(slot, change)| (slot.to_quantity(), change))
+                    .collect();
+                (address, slots)
+            })
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Address, BTreeMap<U256, StorageChange>>, D::Error> {
+        BTreeMap::<Address, BTreeMap<String, StorageChange>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(address, slots)| {
+                let slots = slots
+                    .into_iter()
+                    .map(|(slot, change)| Ok((U256::from_quantity(&slot)?, change)))
+                    .collect::<Result<_, String>>()?;
+                Ok((address, slots))
+            })
+            .collect::<Result<_, String>>()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Serializes contract code by its hash as hex data.
+pub(crate) mod codes {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        codes: &BTreeMap<B256, Bytecode>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        codes
+            .iter()
+            .map(|(code_hash, code)| (code_hash, to_data(code)))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<B256, Bytecode>, D::Error> {
+        BTreeMap::<B256, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(code_hash, code)| Ok((code_hash, Bytecode::from(from_data(&code)?))))
+            .collect::<Result<_, String>>()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::{
+        Account, Changeset, EvmStateSnapshot, EMPTY_CODE_HASH, EMPTY_ROOT_HASH,
+    };
+
+    #[test]
+    fn test_account_is_serialized_with_hex_quantities_and_data() {
+        let account = Account::empty().with_nonce(1).with_balance(U256::exp10(18));
+
+        let actual_json = serde_json::to_value(&account).unwrap();
+
+        assert_eq!(
+            serde_json::json!({
+                "nonce": "0x1",
+                "balance": "0xde0b6b3a7640000",
+                "codeHash": "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+                "storageRoot": "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+            }),
+            actual_json
+        );
+    }
+
+    #[test]
+    fn test_zero_is_serialized_as_single_digit_quantity() {
+        let account = Account::empty();
+
+        let actual_json = serde_json::to_value(&account).unwrap();
+
+        assert_eq!("0x0", actual_json["nonce"]);
+        assert_eq!("0x0", actual_json["balance"]);
+    }
+
+    #[test]
+    fn test_quantity_without_digits_is_not_deserialized() {
+        let json = serde_json::json!({
+            "nonce": "0x",
+            "balance": "0x0",
+            "codeHash": format!("{EMPTY_CODE_HASH:#x}"),
+            "storageRoot": format!("{EMPTY_ROOT_HASH:#x}"),
+        });
+
+        let actual_account = serde_json::from_value::<Account>(json);
+
+        assert!(actual_account.is_err(), "Empty quantity deserialized");
+    }
+
+    #[test]
+    fn test_address_with_checksum_is_deserialized() {
+        let json = serde_json::json!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+
+        let actual_address = serde_json::from_value::<Address>(json).unwrap();
+
+        assert_eq!(
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            format!("{actual_address:#x}")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_serialized_to_json_and_back_is_unchanged() {
+        let address = Address::new([1u8; 20]);
+        let mut snapshot = EvmStateSnapshot::default();
+        snapshot.accounts.insert(address, Account::empty());
+        snapshot
+            .storage
+            .entry(address)
+            .or_default()
+            .insert(U256::one(), U256::from(7));
+        snapshot
+            .codes
+            .insert(B256::from(U256::one()), Bytecode::from(vec![0x60, 0x00]));
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let actual_snapshot = serde_json::from_str::<EvmStateSnapshot>(&json).unwrap();
+
+        assert_eq!(snapshot, actual_snapshot);
+    }
+
+    #[test]
+    fn test_changeset_serialized_to_json_and_back_is_unchanged() {
+        let address = Address::new([1u8; 20]);
+        let mut changeset = Changeset::default();
+        changeset.insert(address, None, Some(Account::empty()));
+        changeset.insert_storage(address, U256::one(), U256::zero(), U256::from(7));
+
+        let json = serde_json::to_value(&changeset).unwrap();
+        let actual_changeset = serde_json::from_value::<Changeset>(json.clone()).unwrap();
+
+        assert_eq!(changeset, actual_changeset);
+        assert_eq!(
+            "0x7",
+            json["storage"][format!("{address:#x}")]["0x1"]["after"]
+        );
+    }
+}
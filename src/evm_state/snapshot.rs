@@ -0,0 +1,60 @@
+//! Point-in-time dumps of a whole EVM state taken from in-memory repositories.
+use crate::evm_state::{trie, Account, Address, Bytecode, B256};
+use primitive_types::U256;
+use std::collections::BTreeMap;
+
+/// A whole EVM state dumped from an in-memory repository, e.g. to be kept as a test fixture.
+///
+/// Entries are ordered by their keys, so that the same state always produces the same dump. With
+/// the `serde` feature enabled, it is (de)serializable using the Ethereum JSON conventions, which
+/// represent numbers as hex quantities and hashes, addresses and code as 0x-prefixed data:
+///
+/// ```json
+/// {
+///   "accounts": {
+///     "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed": {
+///       "nonce": "0x1",
+///       "balance": "0xde0b6b3a7640000",
+///       "codeHash": "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+///       "storageRoot": "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+///     }
+///   },
+///   "storage": {
+///     "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed": { "0x1": "0x7" }
+///   },
+///   "codes": {
+///     "0x...": "0x6000"
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvmStateSnapshot {
+    /// Accounts by their addresses.
+    pub accounts: BTreeMap<Address, Account>,
+    /// Contract storage slots and their values by addresses of the accounts.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::evm_state::serialization::storage")
+    )]
+    pub storage: BTreeMap<Address, BTreeMap<U256, U256>>,
+    /// Contract code by its hash.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::evm_state::serialization::codes")
+    )]
+    pub codes: BTreeMap<B256, Bytecode>,
+}
+
+impl EvmStateSnapshot {
+    /// Computes the state root of the snapshot.
+    ///
+    /// Storage roots of accounts are computed from their contract storage, regardless of the
+    /// storage roots held by the accounts.
+    pub fn state_root(&self) -> B256 {
+        trie::state_root(self.accounts.iter().map(|(address, account)| {
+            let storage_root = trie::storage_root(self.storage.get(address).into_iter().flatten());
+            (*address, account.clone().with_storage_root(storage_root))
+        }))
+    }
+}
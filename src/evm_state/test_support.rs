@@ -0,0 +1,14 @@
+//! Fixtures shared by tests of EVM state modules.
+use crate::evm_state::{Account, Address};
+
+/// Creates an address whose bytes are zero except for the `last_byte`.
+pub(crate) fn address(last_byte: u8) -> Address {
+    let mut address = [0u8; 20];
+    address[19] = last_byte;
+    Address::new(address)
+}
+
+/// Creates an empty account with the `nonce`.
+pub(crate) fn account(nonce: u64) -> Account {
+    Account::empty().with_nonce(nonce)
+}
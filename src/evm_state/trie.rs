@@ -0,0 +1,433 @@
+//! Root hash computation of the [Merkle Patricia trie] holding EVM state.
+//!
+//! In the state trie a path is `keccak256(address)` and a value is `rlp(account)`, where the
+//! account is a list of `[nonce, balance, storageRoot, codeHash]`. In a storage trie of an account
+//! a path is `keccak256(slot)` and a value is `rlp(value)`, while slots holding zero are left out.
+//!
+//! [Merkle Patricia trie]: https://ethereum.org/en/developers/docs/data-structures-and-encoding/patricia-merkle-trie/
+use crate::error::{Error, Result};
+use crate::evm_state::codec::trimmed_bytes;
+use crate::evm_state::primitives::keccak256;
+use crate::evm_state::{Account, Address, B256, EMPTY_ROOT_HASH};
+use primitive_types::U256;
+use rlp::{Rlp, RlpStream};
+use std::collections::BTreeMap;
+
+/// Returns entries of the storage trie holding `storage` slots and their values.
+fn storage_entries<'a>(
+    storage: impl IntoIterator<Item = (&'a U256, &'a U256)>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    storage
+        .into_iter()
+        .filter(|(_slot, value)| !value.is_zero())
+        .map(|(slot, value)| {
+            let value = rlp::encode(&trimmed_bytes(value).as_slice()).to_vec();
+            (storage_key(slot), value)
+        })
+        .collect()
+}
+
+/// Returns entries of the state trie holding `accounts`.
+fn state_entries(
+    accounts: impl IntoIterator<Item = (Address, Account)>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    accounts
+        .into_iter()
+        .map(|(address, account)| (state_key(&address), rlp::encode(&account).to_vec()))
+        .collect()
+}
+
+/// Returns the path of the storage `slot` in a storage trie.
+pub(crate) fn storage_key(slot: &U256) -> Vec<u8> {
+    keccak256(B256::from(*slot)).to_vec()
+}
+
+/// Returns the path of an account with the `address` in the state trie.
+pub(crate) fn state_key(address: &Address) -> Vec<u8> {
+    keccak256(address).to_vec()
+}
+
+/// Computes the root hash of the storage trie holding `storage` slots and their values.
+pub(crate) fn storage_root<'a>(storage: impl IntoIterator<Item = (&'a U256, &'a U256)>) -> B256 {
+    trie_root(storage_entries(storage))
+}
+
+/// Computes the root hash of the state trie holding `accounts`.
+///
+/// Storage roots of the `accounts` are used as they are, so they must be computed beforehand.
+pub(crate) fn state_root(accounts: impl IntoIterator<Item = (Address, Account)>) -> B256 {
+    trie_root(state_entries(accounts))
+}
+
+/// Collects the proof of a storage `slot` in the storage trie holding `storage`.
+pub(crate) fn storage_proof<'a>(
+    storage: impl IntoIterator<Item = (&'a U256, &'a U256)>,
+    slot: &U256,
+) -> Vec<Vec<u8>> {
+    trie_proof(storage_entries(storage), &storage_key(slot))
+}
+
+/// Collects the proof of an account with the `address` in the state trie holding `accounts`.
+pub(crate) fn state_proof(
+    accounts: impl IntoIterator<Item = (Address, Account)>,
+    address: &Address,
+) -> Vec<Vec<u8>> {
+    trie_proof(state_entries(accounts), &state_key(address))
+}
+
+/// Sorts `entries` by their keys split into nibbles and leaves out entries with an empty value,
+/// which are not a part of the trie.
+fn sorted_entries(
+    entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let entries: BTreeMap<Vec<u8>, Vec<u8>> = entries
+        .into_iter()
+        .filter(|(_key, value)| !value.is_empty())
+        .map(|(key, value)| (to_nibbles(&key), value))
+        .collect();
+
+    entries.into_iter().collect()
+}
+
+/// Computes the root hash of a trie holding `entries` of keys and their values. An entry with an
+/// empty value is not a part of the trie.
+pub(crate) fn trie_root(entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> B256 {
+    let entries = sorted_entries(entries);
+
+    B256::new(keccak256(encode_node(&entries, 0, None, &mut Vec::new())))
+}
+
+/// Collects encoded nodes on the path of the `key` from the root of a trie holding `entries`.
+///
+/// Nodes embedded in their parent node are not collected separately, so the proof of an empty trie
+/// is empty.
+pub(crate) fn trie_proof(
+    entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    key: &[u8],
+) -> Vec<Vec<u8>> {
+    let entries = sorted_entries(entries);
+    if entries.is_empty() {
+        return Vec::new();
+    }
+
+    let mut proof = Vec::new();
+    let root = encode_node(&entries, 0, Some(&to_nibbles(key)), &mut proof);
+    proof.push(root);
+    // Nodes are collected from the deepest one
+    proof.reverse();
+    proof
+}
+
+/// Verifies the `proof` of the `key` against the `root` of a trie and returns the value of the key
+/// if the proof shows it exists, or [`None`] if the proof shows it does not.
+pub(crate) fn verify_proof(root: B256, key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>> {
+    if root == EMPTY_ROOT_HASH && proof.is_empty() {
+        return Ok(None);
+    }
+
+    let key = to_nibbles(key);
+    let mut nibbles = key.as_slice();
+    let mut proof = proof.iter();
+    let mut node = next_proof_node(&mut proof, root.as_bytes())?.as_slice();
+
+    loop {
+        let rlp = Rlp::new(node);
+        let child = match rlp.item_count().map_err(Error::invalid_proof)? {
+            2 => {
+                let (path, is_leaf) = decode_hex_prefix(data(&rlp, 0)?)?;
+
+                if is_leaf {
+                    if nibbles != path {
+                        return Ok(None);
+                    }
+                    return Ok(Some(data(&rlp, 1)?.to_vec()));
+                }
+                match nibbles.strip_prefix(path.as_slice()) {
+                    Some(rest) => nibbles = rest,
+                    None => return Ok(None),
+                }
+                rlp.at(1).map_err(Error::invalid_proof)?
+            }
+            17 => match nibbles.split_first() {
+                Some((nibble, rest)) => {
+                    nibbles = rest;
+                    rlp.at(*nibble as usize).map_err(Error::invalid_proof)?
+                }
+                None => {
+                    let value = data(&rlp, 16)?;
+                    return Ok((!value.is_empty()).then(|| value.to_vec()));
+                }
+            },
+            _ => {
+                return Err(Error::invalid_proof(
+                    "node is neither a branch nor a short node",
+                ))
+            }
+        };
+
+        node = if child.is_list() {
+            child.as_raw()
+        } else {
+            match child.data().map_err(Error::invalid_proof)? {
+                [] => return Ok(None),
+                hash => next_proof_node(&mut proof, hash)?,
+            }
+        };
+    }
+}
+
+fn next_proof_node<'a>(
+    proof: &mut impl Iterator<Item = &'a Vec<u8>>,
+    hash: &[u8],
+) -> Result<&'a Vec<u8>> {
+    let node = proof
+        .next()
+        .ok_or_else(|| Error::invalid_proof("proof is missing a node"))?;
+
+    if keccak256(node) != hash {
+        return Err(Error::invalid_proof("node does not match its hash"));
+    }
+
+    Ok(node)
+}
+
+fn data<'a>(rlp: &Rlp<'a>, index: usize) -> Result<&'a [u8]> {
+    rlp.at(index)
+        .and_then(|item| item.data())
+        .map_err(Error::invalid_proof)
+}
+
+/// Decodes a path encoded with the hex-prefix encoding into nibbles and a leaf flag.
+fn decode_hex_prefix(bytes: &[u8]) -> Result<(Vec<u8>, bool)> {
+    let (first, rest) = bytes
+        .split_first()
+        .ok_or_else(|| Error::invalid_proof("path of a node is empty"))?;
+    let flag = first >> 4;
+    let mut nibbles = Vec::with_capacity(rest.len() * 2 + 1);
+
+    if flag & 1 == 1 {
+        nibbles.push(first & 0x0f);
+    }
+    nibbles.extend(to_nibbles(rest));
+
+    Ok((nibbles, flag & 2 == 2))
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// Encodes a path of `nibbles` with the hex-prefix encoding flagging a leaf or an extension.
+fn hex_prefix(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let flag = if is_leaf { 2 } else { 0 };
+    let mut bytes = Vec::with_capacity(nibbles.len() / 2 + 1);
+
+    let rest = if nibbles.len() % 2 == 1 {
+        bytes.push((flag + 1) << 4 | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        bytes.push(flag << 4);
+        nibbles
+    };
+
+    bytes.extend(rest.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+    bytes
+}
+
+/// Encodes a node holding `entries` sorted by their keys, which all share first `depth` nibbles.
+///
+/// Hashed nodes on the `path` below the encoded node are collected into the `proof`, the deepest
+/// node first.
+fn encode_node(
+    entries: &[(Vec<u8>, Vec<u8>)],
+    depth: usize,
+    path: Option<&[u8]>,
+    proof: &mut Vec<Vec<u8>>,
+) -> Vec<u8> {
+    match entries {
+        [] => rlp::NULL_RLP.to_vec(),
+        [(key, value)] => {
+            let mut stream = RlpStream::new_list(2);
+            stream
+                .append(&hex_prefix(&key[depth..], true).as_slice())
+                .append(&value.as_slice());
+            stream.out().to_vec()
+        }
+        [(first, _), .., (last, _)] => {
+            // Keys are sorted, so the first and the last key share the prefix of all keys
+            let prefix = first[depth..]
+                .iter()
+                .zip(&last[depth..])
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            if prefix > 0 {
+                let shared = &first[depth..depth + prefix];
+                let path = path.filter(|path| path.get(depth..depth + prefix) == Some(shared));
+                let mut stream = RlpStream::new_list(2);
+                stream.append(&hex_prefix(shared, false).as_slice());
+                append_child(&mut stream, entries, depth + prefix, path, proof);
+                return stream.out().to_vec();
+            }
+
+            // A key ending at the branch is the shortest, hence sorted first
+            let (value, mut entries) = match entries.split_first() {
+                Some(((key, value), rest)) if key.len() == depth => (Some(value), rest),
+                _ => (None, entries),
+            };
+
+            let mut stream = RlpStream::new_list(17);
+            for nibble in 0..16 {
+                let count = entries
+                    .iter()
+                    .take_while(|(key, _value)| key[depth] == nibble)
+                    .count();
+                let (children, rest) = entries.split_at(count);
+
+                if children.is_empty() {
+                    stream.append_empty_data();
+                } else {
+                    let path = path.filter(|path| path.get(depth) == Some(&nibble));
+                    append_child(&mut stream, children, depth + 1, path, proof);
+                }
+                entries = rest;
+            }
+            match value {
+                Some(value) => stream.append(&value.as_slice()),
+                None => stream.append_empty_data(),
+            };
+            stream.out().to_vec()
+        }
+    }
+}
+
+/// Appends a reference to a child node holding `entries`, which is embedded when its encoding is
+/// shorter than its hash.
+fn append_child(
+    stream: &mut RlpStream,
+    entries: &[(Vec<u8>, Vec<u8>)],
+    depth: usize,
+    path: Option<&[u8]>,
+    proof: &mut Vec<Vec<u8>>,
+) {
+    let encoded = encode_node(entries, depth, path, proof);
+
+    if encoded.len() < 32 {
+        stream.append_raw(&encoded, 1);
+    } else {
+        stream.append(&keccak256(&encoded).as_slice());
+        if path.is_some() {
+            proof.push(encoded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::EMPTY_ROOT_HASH;
+
+    fn root_of(entries: &[(&str, &str)]) -> B256 {
+        trie_root(
+            entries
+                .iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec())),
+        )
+    }
+
+    #[test]
+    fn test_root_of_empty_trie_is_empty_root_hash() {
+        let actual_root = trie_root(Vec::new());
+
+        assert_eq!(EMPTY_ROOT_HASH, actual_root);
+    }
+
+    // The following test vectors come from trieanyorder.json of the Ethereum tests
+    #[test]
+    fn test_root_of_trie_with_single_item_matches_test_vector() {
+        let actual_root = root_of(&[("A", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")]);
+
+        assert_eq!(
+            "0xd23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab"
+                .parse::<B256>()
+                .unwrap(),
+            actual_root
+        );
+    }
+
+    #[test]
+    fn test_root_of_trie_with_branches_and_extensions_matches_test_vector() {
+        let actual_root = root_of(&[
+            ("do", "verb"),
+            ("horse", "stallion"),
+            ("doge", "coin"),
+            ("dog", "puppy"),
+        ]);
+
+        assert_eq!(
+            "0x5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"
+                .parse::<B256>()
+                .unwrap(),
+            actual_root
+        );
+    }
+
+    #[test]
+    fn test_root_of_trie_with_embedded_nodes_matches_test_vector() {
+        let actual_root = root_of(&[("be", "e"), ("dog", "puppy"), ("bed", "d")]);
+
+        assert_eq!(
+            "0x3f67c7a47520f79faa29255d2d3c084a7a6df0453116ed7232ff10277a8be68b"
+                .parse::<B256>()
+                .unwrap(),
+            actual_root
+        );
+    }
+
+    #[test]
+    fn test_root_of_trie_with_shared_prefix_matches_test_vector() {
+        let actual_root = root_of(&[("foo", "bar"), ("food", "bass")]);
+
+        assert_eq!(
+            "0x17beaa1648bafa633cda809c90c04af50fc8aed3cb40d16efbddee6fdf63c4c3"
+                .parse::<B256>()
+                .unwrap(),
+            actual_root
+        );
+    }
+
+    #[test]
+    fn test_root_of_trie_with_key_ending_at_branch_matches_test_vector() {
+        let actual_root = root_of(&[("test", "test"), ("te", "testy")]);
+
+        assert_eq!(
+            "0x8452568af70d8d140f58d941338542f645fcca50094b20f3c3d8c3df49337928"
+                .parse::<B256>()
+                .unwrap(),
+            actual_root
+        );
+    }
+
+    #[test]
+    fn test_entries_with_empty_value_are_left_out_of_trie() {
+        let actual_root = root_of(&[
+            ("do", "verb"),
+            ("ether", ""),
+            ("horse", "stallion"),
+            ("doge", "coin"),
+            ("dog", "puppy"),
+        ]);
+
+        assert_eq!(
+            root_of(&[
+                ("do", "verb"),
+                ("horse", "stallion"),
+                ("doge", "coin"),
+                ("dog", "puppy"),
+            ]),
+            actual_root
+        );
+    }
+}
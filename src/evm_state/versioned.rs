@@ -0,0 +1,540 @@
+//! EVM state versioned by block numbers, which can be read at recent blocks and unwound on chain
+//! reorganizations.
+//!
+//! # Example
+//! ```
+//! use evm_state_cache::{
+//!     Account, Address, CacheBuilder, CachedEvmStateRepository, EvmStateRepository,
+//!     InMemoryEvmStateRepository, VersionedEvmStateRepository,
+//! };
+//!
+//! let cached = CachedEvmStateRepository::new(
+//!     InMemoryEvmStateRepository::default(),
+//!     CacheBuilder::new().with_capacity(10).build_lru(),
+//! );
+//! let mut repository = VersionedEvmStateRepository::new(cached, 0, 64);
+//!
+//! repository.replace(Address::ZERO, Account::empty().with_nonce(1)).unwrap();
+//! repository.commit_block(1).unwrap();
+//! repository.replace(Address::ZERO, Account::empty().with_nonce(2)).unwrap();
+//! repository.commit_block(2).unwrap();
+//!
+//! assert_eq!(1, repository.get_at(&Address::ZERO, 1).unwrap().unwrap().nonce());
+//!
+//! repository.unwind_to(1).unwrap();
+//! assert_eq!(1, repository.get(&Address::ZERO).unwrap().unwrap().nonce());
+//! ```
+use crate::error::{Error, Result};
+use crate::evm_state::{
+    Account, Address, Bytecode, Changeset, EvmStateRepository, JournaledEvmStateRepository, B256,
+};
+use primitive_types::U256;
+use std::collections::VecDeque;
+
+/// An [`EvmStateRepository`] that records a [`Changeset`] of every committed block, so that
+/// accounts can be read at any block within a retention window and the state can be unwound to
+/// any of those blocks, e.g. on a chain reorganization.
+///
+/// Writes are kept in a [`JournaledEvmStateRepository`] until the block is committed by
+/// [`VersionedEvmStateRepository::commit_block`]. Reads see the writes of the pending block, while
+/// [`VersionedEvmStateRepository::get_at`] sees committed blocks only.
+///
+/// Unwinding writes the accounts and storage slots back through the inner repository, so a
+/// [`CachedEvmStateRepository`](crate::CachedEvmStateRepository) used as the inner repository
+/// replaces or invalidates its affected cache entries, including cached storage slots, rather
+/// than serving stale ones.
+///
+/// Contract code is not versioned, as it is addressed by its hash. Unwinding a block that deleted
+/// an account restores its storage as well, as described by [`Changeset`].
+#[derive(Debug)]
+pub struct VersionedEvmStateRepository<R: EvmStateRepository> {
+    state: JournaledEvmStateRepository<R>,
+    head: u64,
+    retention: usize,
+    /// Changesets of the most recent blocks, ordered from the oldest to the head.
+    blocks: VecDeque<Changeset>,
+}
+
+impl<R: EvmStateRepository> VersionedEvmStateRepository<R> {
+    /// Creates a repository whose `inner` repository holds the state at the `head` block, keeping
+    /// changesets of at most `retention` most recent blocks.
+    pub fn new(inner: R, head: u64, retention: usize) -> Self {
+        Self {
+            state: JournaledEvmStateRepository::new(inner),
+            head,
+            retention,
+            blocks: VecDeque::with_capacity(retention),
+        }
+    }
+
+    /// Returns the number of the last committed block.
+    pub fn head(&self) -> u64 {
+        self.head
+    }
+
+    /// Returns the number of the oldest block whose state can still be read or unwound to.
+    pub fn oldest(&self) -> u64 {
+        self.head - self.blocks.len() as u64
+    }
+
+    /// Commits writes made since the last commit as the block with the `number`, which must
+    /// directly follow the head block, and returns its changeset.
+    ///
+    /// Returns [`Error::InvalidInput`] if the block does not follow the head block. The changeset
+    /// of the oldest block is dropped once more than the retention of them are kept.
+    pub fn commit_block(&mut self, number: u64) -> Result<Changeset> {
+        if number != self.head + 1 {
+            return Err(Error::invalid_input(format!(
+                "block {number} does not follow head block {}",
+                self.head
+            )));
+        }
+
+        let changeset = self.state.changeset()?;
+        self.state.commit()?;
+        self.head = number;
+        self.blocks.push_back(changeset.clone());
+        while self.blocks.len() > self.retention {
+            self.blocks.pop_front();
+        }
+
+        Ok(changeset)
+    }
+
+    /// Reads [`Account`] as it was after the block with the `number` was committed.
+    ///
+    /// Returns [`Error::InvalidInput`] if the block is ahead of the head block or older than the
+    /// retention window.
+    pub fn get_at(&self, address: &Address, number: u64) -> Result<Option<Account>> {
+        let changesets = self.blocks_after(number)?;
+        let change = changesets
+            .into_iter()
+            .find_map(|changeset| changeset.accounts.get(address));
+
+        match change {
+            Some(change) => Ok(change.before.clone()),
+            None => self.state.inner().get(address),
+        }
+    }
+
+    /// Unwinds the state to the block with the `number`, discarding writes of the pending block
+    /// and undoing every committed block after it. Returns the changeset that was applied to undo
+    /// them, e.g. to invalidate entries of caches beyond the inner repository.
+    ///
+    /// Returns [`Error::InvalidInput`] if the block is ahead of the head block or older than the
+    /// retention window.
+    ///
+    /// The rollback is written into the inner repository by a sequence of writes, which is not
+    /// atomic. If one of them fails, the head block and the retained changesets stay unchanged,
+    /// but the inner repository may already hold a part of the rollback, so the state is
+    /// inconsistent until unwinding is retried and succeeds. Since the rollback writes the values
+    /// at the block regardless of the current ones, a retry completes what the failed attempt
+    /// started.
+    pub fn unwind_to(&mut self, number: u64) -> Result<Changeset> {
+        let unwound_blocks = self.blocks_after(number)?.count();
+
+        let mut rollback = Changeset::default();
+        for changeset in self.blocks.iter().rev().take(unwound_blocks) {
+            rollback.extend(changeset.clone().inverse());
+        }
+        self.state.discard();
+        if let Err(error) = rollback
+            .apply_to(&mut self.state)
+            .and_then(|()| self.state.commit())
+        {
+            self.state.discard();
+            return Err(error);
+        }
+
+        self.blocks.truncate(self.blocks.len() - unwound_blocks);
+        self.head = number;
+
+        Ok(rollback)
+    }
+
+    /// Returns the inner repository, discarding writes of the pending block.
+    pub fn into_inner(self) -> R {
+        self.state.into_inner()
+    }
+
+    /// Returns changesets of blocks committed after the block with the `number`, from the oldest.
+    fn blocks_after(&self, number: u64) -> Result<impl Iterator<Item = &Changeset>> {
+        if number > self.head || number < self.oldest() {
+            return Err(Error::invalid_input(format!(
+                "block {number} is outside of retained blocks {} to {}",
+                self.oldest(),
+                self.head
+            )));
+        }
+
+        Ok(self.blocks.range((number - self.oldest()) as usize..))
+    }
+}
+
+impl<R: EvmStateRepository> EvmStateRepository for VersionedEvmStateRepository<R> {
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        self.state.get(address)
+    }
+
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        self.state.get_many(addresses)
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        self.state.replace(address, account)
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        self.state.replace_many(accounts)
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        self.state.delete(address)
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        self.state.get_storage(address, slot)
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        self.state.replace_storage(address, slot, value)
+    }
+
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        self.state.get_all_storage(address)
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        self.state.get_code(code_hash)
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        self.state.replace_code(code_hash, code)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.state.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_state::test_support::{account, address};
+    use crate::evm_state::{CachedEvmStateRepository, InMemoryEvmStateRepository};
+    use crate::CacheBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Rejects writes once `writes_left` run out.
+    struct FailingWritesEvmRepository {
+        inner: InMemoryEvmStateRepository,
+        writes_left: Arc<AtomicUsize>,
+    }
+
+    impl FailingWritesEvmRepository {
+        fn new() -> Self {
+            Self {
+                inner: InMemoryEvmStateRepository::default(),
+                writes_left: Arc::new(AtomicUsize::new(usize::MAX)),
+            }
+        }
+
+        fn check(&self) -> Result<()> {
+            self.writes_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .map(|_left| ())
+                .map_err(|_left| Error::backend("Database is read-only"))
+        }
+    }
+
+    impl EvmStateRepository for FailingWritesEvmRepository {
+        fn get(&self, address: &Address) -> Result<Option<Account>> {
+            self.inner.get(address)
+        }
+
+        fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+            self.check()?;
+            self.inner.replace(address, account)
+        }
+
+        fn delete(&mut self, address: &Address) -> Result<()> {
+            self.check()?;
+            self.inner.delete(address)
+        }
+
+        fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+            self.inner.get_storage(address, slot)
+        }
+
+        fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+            self.inner.get_all_storage(address)
+        }
+
+        fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+            self.check()?;
+            self.inner.replace_storage(address, slot, value)
+        }
+
+        fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+            self.inner.get_code(code_hash)
+        }
+
+        fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+            self.check()?;
+            self.inner.replace_code(code_hash, code)
+        }
+    }
+
+    /// Creates a repository with blocks 1 to 3, each of which sets the nonce of `address(1)` to
+    /// the block number, while block 2 also creates `address(2)`.
+    fn repository(retention: usize) -> VersionedEvmStateRepository<InMemoryEvmStateRepository> {
+        let mut repository =
+            VersionedEvmStateRepository::new(InMemoryEvmStateRepository::default(), 0, retention);
+
+        for number in 1..=3 {
+            repository.replace(address(1), account(number)).unwrap();
+            if number == 2 {
+                repository.replace(address(2), account(0)).unwrap();
+            }
+            repository.commit_block(number).unwrap();
+        }
+
+        repository
+    }
+
+    #[test]
+    fn test_account_is_read_at_retained_blocks() {
+        let repository = repository(10);
+
+        assert_eq!(None, repository.get_at(&address(1), 0).unwrap());
+        assert_eq!(Some(account(1)), repository.get_at(&address(1), 1).unwrap());
+        assert_eq!(None, repository.get_at(&address(2), 1).unwrap());
+        assert_eq!(Some(account(0)), repository.get_at(&address(2), 2).unwrap());
+        assert_eq!(Some(account(3)), repository.get_at(&address(1), 3).unwrap());
+    }
+
+    #[test]
+    fn test_account_at_head_block_does_not_see_pending_writes() {
+        let mut repository = repository(10);
+
+        repository.replace(address(1), account(4)).unwrap();
+
+        assert_eq!(Some(account(4)), repository.get(&address(1)).unwrap());
+        assert_eq!(Some(account(3)), repository.get_at(&address(1), 3).unwrap());
+    }
+
+    #[test]
+    fn test_blocks_outside_of_retention_window_are_rejected() {
+        let repository = repository(2);
+
+        assert_eq!(1, repository.oldest());
+        assert!(
+            matches!(
+                repository.get_at(&address(1), 0),
+                Err(Error::InvalidInput(_))
+            ),
+            "Block older than retention window is read"
+        );
+        assert!(
+            matches!(
+                repository.get_at(&address(1), 4),
+                Err(Error::InvalidInput(_))
+            ),
+            "Block ahead of head block is read"
+        );
+    }
+
+    #[test]
+    fn test_block_not_following_head_block_is_rejected() {
+        let mut repository = repository(10);
+
+        let result = repository.commit_block(5);
+
+        assert!(
+            matches!(result, Err(Error::InvalidInput(_))),
+            "Block with gap is committed"
+        );
+        assert_eq!(3, repository.head());
+    }
+
+    #[test]
+    fn test_unwound_state_matches_state_at_block() {
+        let mut repository = repository(10);
+        repository.replace(address(3), account(3)).unwrap();
+
+        let rollback = repository.unwind_to(1).unwrap();
+
+        assert_eq!(1, repository.head());
+        assert_eq!(2, rollback.len());
+        assert_eq!(Some(account(1)), repository.get(&address(1)).unwrap());
+        assert_eq!(None, repository.get(&address(2)).unwrap());
+        assert_eq!(None, repository.get(&address(3)).unwrap());
+        assert!(
+            matches!(
+                repository.get_at(&address(1), 2),
+                Err(Error::InvalidInput(_))
+            ),
+            "Unwound block is read"
+        );
+    }
+
+    #[test]
+    fn test_failed_unwinding_leaves_state_at_head_block() {
+        let inner = FailingWritesEvmRepository::new();
+        let writes_left = Arc::clone(&inner.writes_left);
+        let mut repository = VersionedEvmStateRepository::new(inner, 0, 10);
+        for number in 1..=2 {
+            repository.replace(address(1), account(number)).unwrap();
+            repository.commit_block(number).unwrap();
+        }
+        writes_left.store(0, Ordering::SeqCst);
+
+        let result = repository.unwind_to(1);
+        writes_left.store(usize::MAX, Ordering::SeqCst);
+        repository.replace(address(2), account(3)).unwrap();
+        let changeset = repository.commit_block(3).unwrap();
+
+        assert!(
+            matches!(result, Err(Error::Backend(_))),
+            "Unwinding succeeded"
+        );
+        assert_eq!(Some(account(2)), repository.get(&address(1)).unwrap());
+        assert_eq!(1, changeset.len());
+    }
+
+    #[test]
+    fn test_retried_unwinding_completes_partially_written_rollback() {
+        let inner = FailingWritesEvmRepository::new();
+        let writes_left = Arc::clone(&inner.writes_left);
+        let mut repository = VersionedEvmStateRepository::new(inner, 0, 10);
+        for number in 1..=2 {
+            repository.replace(address(1), account(number)).unwrap();
+            repository.replace(address(2), account(number)).unwrap();
+            repository.commit_block(number).unwrap();
+        }
+        writes_left.store(1, Ordering::SeqCst);
+
+        let result = repository.unwind_to(1);
+        writes_left.store(usize::MAX, Ordering::SeqCst);
+        let head_after_failure = repository.head();
+        let mut partial_accounts = repository.get_many(&[address(1), address(2)]).unwrap();
+        partial_accounts.sort_by_key(|account| account.as_ref().map(Account::nonce));
+        repository.unwind_to(1).unwrap();
+
+        assert!(
+            matches!(result, Err(Error::Backend(_))),
+            "Unwinding succeeded"
+        );
+        assert_eq!(2, head_after_failure);
+        assert_eq!(vec![Some(account(1)), Some(account(2))], partial_accounts);
+        assert_eq!(1, repository.head());
+        assert_eq!(Some(account(1)), repository.get(&address(1)).unwrap());
+        assert_eq!(Some(account(1)), repository.get(&address(2)).unwrap());
+    }
+
+    #[test]
+    fn test_unwinding_self_destruct_restores_account_with_its_storage() {
+        let mut repository =
+            VersionedEvmStateRepository::new(InMemoryEvmStateRepository::default(), 0, 10);
+        repository.replace(address(1), account(1)).unwrap();
+        repository
+            .replace_storage(address(1), U256::one(), U256::from(7))
+            .unwrap();
+        repository
+            .replace_storage(address(1), U256::from(2), U256::from(8))
+            .unwrap();
+        repository.commit_block(1).unwrap();
+        repository.delete(&address(1)).unwrap();
+        repository.commit_block(2).unwrap();
+
+        repository.unwind_to(1).unwrap();
+        let inner = repository.into_inner();
+
+        assert_eq!(Some(account(1)), inner.get(&address(1)).unwrap());
+        assert_eq!(
+            U256::from(7),
+            inner.get_storage(&address(1), &U256::one()).unwrap()
+        );
+        assert_eq!(
+            U256::from(8),
+            inner.get_storage(&address(1), &U256::from(2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_blocks_are_committed_after_unwinding() {
+        let mut repository = repository(10);
+        repository.unwind_to(1).unwrap();
+
+        repository.replace(address(1), account(5)).unwrap();
+        repository.commit_block(2).unwrap();
+
+        assert_eq!(Some(account(1)), repository.get_at(&address(1), 1).unwrap());
+        assert_eq!(Some(account(5)), repository.get_at(&address(1), 2).unwrap());
+    }
+
+    #[test]
+    fn test_unwinding_replaces_stale_entries_of_inner_cache() {
+        let cached = CachedEvmStateRepository::new(
+            InMemoryEvmStateRepository::default(),
+            CacheBuilder::new().with_capacity(10).build_lru(),
+        );
+        let mut repository = VersionedEvmStateRepository::new(cached, 0, 10);
+        repository.replace(address(1), account(1)).unwrap();
+        repository.commit_block(1).unwrap();
+        repository.replace(address(1), account(2)).unwrap();
+        repository.replace(address(2), account(2)).unwrap();
+        repository.commit_block(2).unwrap();
+
+        repository.unwind_to(1).unwrap();
+
+        let cached = repository.into_inner();
+        assert_eq!(Some(account(1)), cached.get(&address(1)).unwrap());
+        assert_eq!(None, cached.get(&address(2)).unwrap());
+    }
+
+    #[test]
+    fn test_unwinding_restores_storage_cached_by_inner_repository() {
+        let cached = CachedEvmStateRepository::new(
+            InMemoryEvmStateRepository::default(),
+            CacheBuilder::new().with_capacity(10).build_lru(),
+        )
+        .with_storage_cache(CacheBuilder::new().with_capacity(10).build_lru());
+        let mut repository = VersionedEvmStateRepository::new(cached, 0, 10);
+        for address in [address(1), address(2)] {
+            repository.replace(address, account(1)).unwrap();
+            repository
+                .replace_storage(address, U256::one(), U256::from(1))
+                .unwrap();
+        }
+        repository.commit_block(1).unwrap();
+        repository
+            .replace_storage(address(1), U256::one(), U256::from(2))
+            .unwrap();
+        repository
+            .replace_storage(address(2), U256::one(), U256::from(2))
+            .unwrap();
+        repository.delete(&address(2)).unwrap();
+        repository.commit_block(2).unwrap();
+        assert_eq!(
+            U256::from(2),
+            repository.get_storage(&address(1), &U256::one()).unwrap()
+        );
+
+        repository.unwind_to(1).unwrap();
+
+        let cached = repository.into_inner();
+        assert_eq!(
+            U256::from(1),
+            cached.get_storage(&address(1), &U256::one()).unwrap()
+        );
+        assert_eq!(Some(account(1)), cached.get(&address(2)).unwrap());
+        assert_eq!(
+            U256::from(1),
+            cached.get_storage(&address(2), &U256::one()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,581 @@
+//! Deferral of writes to an [`EvmStateRepository`] used by the write-back mode of
+//! [`CachedEvmStateRepository`](crate::CachedEvmStateRepository).
+use crate::error::Result;
+use crate::evm_state::{
+    check_code_hash, Account, Address, Bytecode, Changeset, ConcurrentEvmStateRepository,
+    EvmStateRepository, B256,
+};
+use primitive_types::U256;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Writes made since the last flush that are yet to be persisted.
+#[derive(Debug, Default, Clone)]
+pub(super) struct DirtyState {
+    /// Addresses deleted since the last flush, whose storage is wiped before any other write.
+    pub(super) deleted: HashSet<Address>,
+    /// Written accounts, where [`None`] stands for a deleted one.
+    pub(super) accounts: HashMap<Address, Option<Account>>,
+    pub(super) storage: HashMap<(Address, U256), U256>,
+    pub(super) codes: HashMap<B256, Bytecode>,
+}
+
+impl DirtyState {
+    fn len(&self) -> usize {
+        self.deleted.len() + self.accounts.len() + self.storage.len() + self.codes.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads accounts of all `addresses`, loading those that were not written from the
+    /// `repository` in a single batch.
+    pub(super) fn get_many(
+        &self,
+        repository: &impl EvmStateRepository,
+        addresses: &[Address],
+    ) -> Result<Vec<Option<Account>>> {
+        let dirty_accounts: Vec<_> = addresses
+            .iter()
+            .map(|address| self.accounts.get(address).cloned())
+            .collect();
+        let misses: Vec<_> = addresses
+            .iter()
+            .zip(&dirty_accounts)
+            .filter(|(_address, account)| account.is_none())
+            .map(|(address, _account)| *address)
+            .collect();
+        let mut loaded_accounts = repository.get_many(&misses)?.into_iter();
+
+        Ok(dirty_accounts
+            .into_iter()
+            .map(|account| account.unwrap_or_else(|| loaded_accounts.next().flatten()))
+            .collect())
+    }
+
+    /// Compares written accounts, which are read in a single batch, and written storage slots
+    /// with their values in the `repository`. Every slot that deleted accounts hold in the
+    /// `repository` is compared as well, since the delete wipes it.
+    pub(super) fn changeset(&self, repository: &impl EvmStateRepository) -> Result<Changeset> {
+        let addresses: Vec<_> = self.accounts.keys().copied().collect();
+        let mut changeset = Changeset::default();
+
+        for (address, before) in addresses.iter().zip(repository.get_many(&addresses)?) {
+            changeset.insert(*address, before, self.accounts[address].clone());
+        }
+        let mut slots: HashSet<_> = self.storage.keys().copied().collect();
+        for address in &self.deleted {
+            let storage = repository.get_all_storage(address)?;
+            slots.extend(storage.into_iter().map(|(slot, _value)| (*address, slot)));
+        }
+        for (address, slot) in slots {
+            let before = repository.get_storage(&address, &slot)?;
+            let after = self.get_storage(&address, &slot).unwrap_or(before);
+            changeset.insert_storage(address, slot, before, after);
+        }
+
+        Ok(changeset)
+    }
+
+    /// Returns the written value of the storage `slot`, which is zero for a deleted account, or
+    /// [`None`] if it has to be read from the repository.
+    pub(super) fn get_storage(&self, address: &Address, slot: &U256) -> Option<U256> {
+        match self.storage.get(&(*address, *slot)) {
+            Some(value) => Some(*value),
+            None if self.deleted.contains(address) => Some(U256::zero()),
+            None => None,
+        }
+    }
+
+    /// Applies the writes to the `storage` of an account with the `address` read from below, where
+    /// a delete wipes what was read and slots holding zero are dropped.
+    pub(super) fn overlay_storage(
+        &self,
+        address: &Address,
+        storage: Vec<(U256, U256)>,
+    ) -> Vec<(U256, U256)> {
+        let mut storage: HashMap<_, _> = match self.deleted.contains(address) {
+            true => HashMap::new(),
+            false => storage.into_iter().collect(),
+        };
+        storage.extend(
+            self.storage
+                .iter()
+                .filter(|((slot_address, _slot), _value)| slot_address == address)
+                .map(|((_address, slot), value)| (*slot, *value)),
+        );
+
+        storage
+            .into_iter()
+            .filter(|(_slot, value)| !value.is_zero())
+            .collect()
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) {
+        self.accounts.extend(
+            accounts
+                .into_iter()
+                .map(|(address, account)| (address, Some(account))),
+        );
+    }
+
+    pub(super) fn delete(&mut self, address: &Address) {
+        self.deleted.insert(*address);
+        self.accounts.insert(*address, None);
+        self.storage
+            .retain(|(slot_address, _slot), _value| slot_address != address);
+    }
+
+    /// Applies `newer` writes on top of these ones.
+    fn merge(&mut self, newer: DirtyState) {
+        for address in &newer.deleted {
+            self.delete(address);
+        }
+        self.accounts.extend(newer.accounts);
+        self.storage.extend(newer.storage);
+        self.codes.extend(newer.codes);
+    }
+
+    /// Writes everything into the `repository` and clears itself.
+    ///
+    /// Nothing is cleared on failure and the flush can be retried.
+    pub(super) fn flush_into(&mut self, repository: &mut impl EvmStateRepository) -> Result<()> {
+        self.persist_into(repository)?;
+        *self = Self::default();
+
+        Ok(())
+    }
+
+    /// Writes everything into the `repository`.
+    ///
+    /// A failed write can be retried, as writing the same state again yields the same result.
+    fn persist_into(&self, repository: &mut impl EvmStateRepository) -> Result<()> {
+        for (code_hash, code) in &self.codes {
+            repository.replace_code(*code_hash, code.clone())?;
+        }
+        for address in &self.deleted {
+            repository.delete(address)?;
+        }
+        repository.replace_many(
+            self.accounts
+                .iter()
+                .filter_map(|(address, account)| Some((*address, account.clone()?)))
+                .collect(),
+        )?;
+        for ((address, slot), value) in &self.storage {
+            repository.replace_storage(*address, *slot, *value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A bounded buffer of [`DirtyState`].
+///
+/// A flush moves the buffered writes aside and persists them while neither reads nor writes wait
+/// for it. Writes being flushed are still read back until they are persisted.
+#[derive(Debug)]
+struct WriteBack {
+    capacity: usize,
+    /// Writes made since the last flush started.
+    dirty: RwLock<DirtyState>,
+    /// Writes being flushed, or left over by a failed flush to be retried by the next one.
+    flushing: RwLock<DirtyState>,
+    /// Serializes flushes, so that writes are persisted in the order they were made.
+    flush: Mutex<()>,
+}
+
+impl WriteBack {
+    fn new(capacity: usize, dirty: DirtyState) -> Self {
+        Self {
+            capacity,
+            dirty: RwLock::new(dirty),
+            flushing: RwLock::default(),
+            flush: Mutex::default(),
+        }
+    }
+
+    fn read(lock: &RwLock<DirtyState>) -> RwLockReadGuard<'_, DirtyState> {
+        lock.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_lock(lock: &RwLock<DirtyState>) -> RwLockWriteGuard<'_, DirtyState> {
+        lock.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the written account, where [`None`] stands for a deleted one, or [`None`] if it
+    /// has to be read from the repository.
+    fn account(&self, address: &Address) -> Option<Option<Account>> {
+        let dirty = Self::read(&self.dirty);
+        let flushing = Self::read(&self.flushing);
+
+        dirty
+            .accounts
+            .get(address)
+            .or_else(|| flushing.accounts.get(address))
+            .cloned()
+    }
+
+    fn storage(&self, address: &Address, slot: &U256) -> Option<U256> {
+        let dirty = Self::read(&self.dirty);
+        let flushing = Self::read(&self.flushing);
+
+        dirty
+            .get_storage(address, slot)
+            .or_else(|| flushing.get_storage(address, slot))
+    }
+
+    fn code(&self, code_hash: &B256) -> Option<Bytecode> {
+        let dirty = Self::read(&self.dirty);
+        let flushing = Self::read(&self.flushing);
+
+        dirty
+            .codes
+            .get(code_hash)
+            .or_else(|| flushing.codes.get(code_hash))
+            .cloned()
+    }
+
+    /// Reads accounts of all `addresses`, loading those that were not written from the
+    /// `repository` in a single batch.
+    fn get_many(
+        &self,
+        repository: &impl EvmStateRepository,
+        addresses: &[Address],
+    ) -> Result<Vec<Option<Account>>> {
+        let written_accounts: Vec<_> = {
+            let dirty = Self::read(&self.dirty);
+            let flushing = Self::read(&self.flushing);
+
+            addresses
+                .iter()
+                .map(|address| {
+                    dirty
+                        .accounts
+                        .get(address)
+                        .or_else(|| flushing.accounts.get(address))
+                        .cloned()
+                })
+                .collect()
+        };
+        let misses: Vec<_> = addresses
+            .iter()
+            .zip(&written_accounts)
+            .filter(|(_address, account)| account.is_none())
+            .map(|(address, _account)| *address)
+            .collect();
+        let mut loaded_accounts = repository.get_many(&misses)?.into_iter();
+
+        Ok(written_accounts
+            .into_iter()
+            .map(|account| account.unwrap_or_else(|| loaded_accounts.next().flatten()))
+            .collect())
+    }
+
+    /// Reads storage of an account with the `address` from the `repository` and applies the
+    /// buffered writes to it. A flush in progress is waited for, so that no write is applied twice
+    /// or missed.
+    fn get_all_storage(
+        &self,
+        repository: &impl EvmStateRepository,
+        address: &Address,
+    ) -> Result<Vec<(U256, U256)>> {
+        let _flush = self.flush.lock().unwrap_or_else(PoisonError::into_inner);
+        let storage = repository.get_all_storage(address)?;
+        let storage = Self::read(&self.flushing).overlay_storage(address, storage);
+
+        Ok(Self::read(&self.dirty).overlay_storage(address, storage))
+    }
+
+    /// Compares buffered writes with their values in the `repository`. A flush in progress is
+    /// waited for, so that the `repository` holds everything but the buffered writes.
+    fn changeset(&self, repository: &impl EvmStateRepository) -> Result<Changeset> {
+        let _flush = self.flush.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut written = Self::read(&self.flushing).clone();
+        written.merge(Self::read(&self.dirty).clone());
+
+        written.changeset(repository)
+    }
+
+    fn is_dirty(&self) -> bool {
+        !Self::read(&self.dirty).is_empty() || !Self::read(&self.flushing).is_empty()
+    }
+
+    /// Records a write, flushing the buffer into the `repository` first if it is full.
+    ///
+    /// A failed flush rejects the write, so that a write is either recorded or reported as failed.
+    fn write(
+        &self,
+        repository: &mut impl EvmStateRepository,
+        write: impl FnOnce(&mut DirtyState),
+    ) -> Result<()> {
+        if Self::read(&self.dirty).len() >= self.capacity {
+            self.flush_into(repository)?;
+        }
+        write(&mut Self::write_lock(&self.dirty));
+
+        Ok(())
+    }
+
+    /// Moves the buffered writes aside and persists them into the `repository` without blocking
+    /// reads and writes.
+    ///
+    /// Writes are kept aside on failure and the next flush retries them.
+    fn flush_into(&self, repository: &mut impl EvmStateRepository) -> Result<()> {
+        let _flush = self.flush.lock().unwrap_or_else(PoisonError::into_inner);
+        {
+            let mut dirty = Self::write_lock(&self.dirty);
+            let mut flushing = Self::write_lock(&self.flushing);
+            flushing.merge(std::mem::take(&mut *dirty));
+        }
+
+        // Only flushes replace the writes being flushed, so reads are not blocked meanwhile
+        Self::read(&self.flushing).persist_into(repository)?;
+        *Self::write_lock(&self.flushing) = DirtyState::default();
+
+        Ok(())
+    }
+}
+
+/// An [`EvmStateRepository`] that either writes through to the inner repository or, in the
+/// write-back mode, defers writes until they are flushed.
+///
+/// Deferred writes are kept aside from any cache, so they are read back before the inner
+/// repository is reached even once a cache evicts them. They are flushed in a batch when
+/// [`EvmStateRepository::flush`] is called, when the buffer is full, when a cache reports the
+/// eviction of a dirty account by [`Self::flush_evicted`], or when the repository is dropped.
+/// Errors of the flush on drop cannot be returned and are only logged, so the repository should
+/// be flushed explicitly beforehand, which [`Self::is_dirty`] helps to check.
+#[derive(Debug)]
+pub(crate) struct WriteBackRepository<R: EvmStateRepository> {
+    pub(crate) inner: R,
+    write_back: Option<WriteBack>,
+}
+
+impl<R: EvmStateRepository> WriteBackRepository<R> {
+    /// Creates a repository that writes through to the `inner` repository.
+    pub(crate) fn write_through(inner: R) -> Self {
+        Self {
+            inner,
+            write_back: None,
+        }
+    }
+
+    /// Defers writes until `capacity` of them are buffered. Writes buffered so far are kept.
+    pub(crate) fn with_write_back(mut self, capacity: usize) -> Self {
+        let mut dirty = DirtyState::default();
+        if let Some(write_back) = self.write_back.take() {
+            for buffer in [write_back.flushing, write_back.dirty] {
+                dirty.merge(buffer.into_inner().unwrap_or_else(PoisonError::into_inner));
+            }
+        }
+        self.write_back = Some(WriteBack::new(capacity, dirty));
+        self
+    }
+
+    /// Returns changes of accounts and storage that are yet to be flushed, which is none in the
+    /// write-through mode.
+    pub(crate) fn changeset(&self) -> Result<Changeset> {
+        match &self.write_back {
+            Some(write_back) => write_back.changeset(&self.inner),
+            None => Ok(Changeset::default()),
+        }
+    }
+
+    /// Returns `true` if there are deferred writes that are yet to be flushed.
+    pub(crate) fn is_dirty(&self) -> bool {
+        matches!(&self.write_back, Some(write_back) if write_back.is_dirty())
+    }
+
+    /// Returns `true` in the write-back mode.
+    pub(crate) fn is_write_back(&self) -> bool {
+        self.write_back.is_some()
+    }
+
+    /// Flushes deferred writes into the inner repository if an account at any of the `evicted`
+    /// addresses, which were evicted from a cache, is yet to be flushed.
+    ///
+    /// Writes are kept on failure and the next flush retries them.
+    pub(crate) fn flush_evicted(&mut self, evicted: &[Address]) -> Result<()> {
+        match &self.write_back {
+            Some(write_back)
+                if evicted
+                    .iter()
+                    .any(|address| write_back.account(address).is_some()) =>
+            {
+                write_back.flush_into(&mut self.inner)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<R: EvmStateRepository> EvmStateRepository for WriteBackRepository<R> {
+    fn get(&self, address: &Address) -> Result<Option<Account>> {
+        if let Some(account) = self
+            .write_back
+            .as_ref()
+            .and_then(|write_back| write_back.account(address))
+        {
+            return Ok(account);
+        }
+
+        self.inner.get(address)
+    }
+
+    fn get_many(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        match &self.write_back {
+            Some(write_back) => write_back.get_many(&self.inner, addresses),
+            None => self.inner.get_many(addresses),
+        }
+    }
+
+    fn replace(&mut self, address: Address, account: Account) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut self.inner, |dirty| {
+                dirty.accounts.insert(address, Some(account));
+            }),
+            None => self.inner.replace(address, account),
+        }
+    }
+
+    fn replace_many(&mut self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => {
+                write_back.write(&mut self.inner, |dirty| dirty.replace_many(accounts))
+            }
+            None => self.inner.replace_many(accounts),
+        }
+    }
+
+    fn delete(&mut self, address: &Address) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut self.inner, |dirty| dirty.delete(address)),
+            None => self.inner.delete(address),
+        }
+    }
+
+    fn get_storage(&self, address: &Address, slot: &U256) -> Result<U256> {
+        if let Some(value) = self
+            .write_back
+            .as_ref()
+            .and_then(|write_back| write_back.storage(address, slot))
+        {
+            return Ok(value);
+        }
+
+        self.inner.get_storage(address, slot)
+    }
+
+    fn get_all_storage(&self, address: &Address) -> Result<Vec<(U256, U256)>> {
+        match &self.write_back {
+            Some(write_back) => write_back.get_all_storage(&self.inner, address),
+            None => self.inner.get_all_storage(address),
+        }
+    }
+
+    fn replace_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut self.inner, |dirty| {
+                dirty.storage.insert((address, slot), value);
+            }),
+            None => self.inner.replace_storage(address, slot, value),
+        }
+    }
+
+    fn get_code(&self, code_hash: &B256) -> Result<Option<Bytecode>> {
+        if let Some(code) = self
+            .write_back
+            .as_ref()
+            .and_then(|write_back| write_back.code(code_hash))
+        {
+            return Ok(Some(code));
+        }
+
+        self.inner.get_code(code_hash)
+    }
+
+    fn replace_code(&mut self, code_hash: B256, code: Bytecode) -> Result<()> {
+        check_code_hash(&code_hash, &code)?;
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut self.inner, |dirty| {
+                dirty.codes.insert(code_hash, code);
+            }),
+            None => self.inner.replace_code(code_hash, code),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(write_back) = &self.write_back {
+            write_back.flush_into(&mut self.inner)?;
+        }
+
+        self.inner.flush()
+    }
+}
+
+impl<R: ConcurrentEvmStateRepository> ConcurrentEvmStateRepository for WriteBackRepository<R> {
+    fn replace(&self, address: Address, account: Account) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut &self.inner, |dirty| {
+                dirty.accounts.insert(address, Some(account));
+            }),
+            None => ConcurrentEvmStateRepository::replace(&self.inner, address, account),
+        }
+    }
+
+    fn replace_many(&self, accounts: Vec<(Address, Account)>) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => {
+                write_back.write(&mut &self.inner, |dirty| dirty.replace_many(accounts))
+            }
+            None => ConcurrentEvmStateRepository::replace_many(&self.inner, accounts),
+        }
+    }
+
+    fn delete(&self, address: &Address) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut &self.inner, |dirty| dirty.delete(address)),
+            None => ConcurrentEvmStateRepository::delete(&self.inner, address),
+        }
+    }
+
+    fn replace_storage(&self, address: Address, slot: U256, value: U256) -> Result<()> {
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut &self.inner, |dirty| {
+                dirty.storage.insert((address, slot), value);
+            }),
+            None => {
+                ConcurrentEvmStateRepository::replace_storage(&self.inner, address, slot, value)
+            }
+        }
+    }
+
+    fn replace_code(&self, code_hash: B256, code: Bytecode) -> Result<()> {
+        check_code_hash(&code_hash, &code)?;
+        match &self.write_back {
+            Some(write_back) => write_back.write(&mut &self.inner, |dirty| {
+                dirty.codes.insert(code_hash, code);
+            }),
+            None => ConcurrentEvmStateRepository::replace_code(&self.inner, code_hash, code),
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        if let Some(write_back) = &self.write_back {
+            write_back.flush_into(&mut &self.inner)?;
+        }
+
+        ConcurrentEvmStateRepository::flush(&self.inner)
+    }
+}
+
+impl<R: EvmStateRepository> Drop for WriteBackRepository<R> {
+    fn drop(&mut self) {
+        // Errors cannot be returned from drop, an explicit flush should precede it
+        if let Err(error) = EvmStateRepository::flush(self) {
+            log::error!("Deferred writes were lost on drop: {error}");
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //! A module that provides creation responsible interfaces.
-use crate::cache::Cache;
-use crate::evm_state::{Account, Address};
+use crate::cache::{Cache, ConcurrentCache, LruCache};
 use std::fmt::Debug;
+use std::hash::Hash;
 use std::marker::PhantomData;
 
 /// The eviction (and admission) policy of a cache.
@@ -67,6 +67,39 @@ impl<State: Debug + Default> CacheBuilder<State> {
     }
 }
 
+impl CacheBuilder<WithCapacity<()>> {
+    /// Builds a [`Cache`] implementation that evicts exactly the least recently used entry as soon
+    /// as the capacity is exceeded.
+    ///
+    /// Unlike the cache created by [`CacheBuilder::build`], which enforces the capacity
+    /// asynchronously, the eviction is deterministic. It is therefore suitable for reproducible
+    /// runs, such as replay tests.
+    pub fn build_lru<K, V>(self) -> impl Cache<K, V>
+    where
+        K: Hash + Eq + Clone,
+        V: Clone,
+    {
+        self.build_sharded_lru(1)
+    }
+
+    /// Builds a [`Cache`] implementation like [`CacheBuilder::build_lru`] that splits its entries
+    /// and capacity evenly into a number of `shards` to lower contention of concurrent access.
+    ///
+    /// Each shard evicts its own least recently used entry, which is not necessarily the least
+    /// recently used entry of the whole cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is lower than the number of `shards` or there are no `shards`.
+    pub fn build_sharded_lru<K, V>(self, shards: usize) -> impl Cache<K, V>
+    where
+        K: Hash + Eq + Clone,
+        V: Clone,
+    {
+        LruCache::new(self.capacity.expect("Parameters are filled-in"), shards)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct WithCapacity<T: Debug + Default>(PhantomData<T>);
 
@@ -87,17 +120,45 @@ impl<State: Debug + Default + HasCapacity> HasCapacity for CacheBuilder<State> {
 
 impl<State: Debug + Default + HasCapacity + HasPolicy> CacheBuilder<State> {
     /// Builds a [`Cache`] implementation according to parameters set on the builder.
-    pub fn build(self) -> impl Cache<Address, Account> {
-        moka::sync::CacheBuilder::new(self.capacity.expect("Parameters are filled-in") as u64)
-            .eviction_policy(self.policy.expect("Parameters are filled-in").into())
-            .build()
+    pub fn build<K, V>(self) -> impl Cache<K, V>
+    where
+        K: Hash + Eq + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        ConcurrentCache::new(
+            moka::sync::CacheBuilder::new(self.capacity.expect("Parameters are filled-in") as u64)
+                .eviction_policy(self.policy.expect("Parameters are filled-in").into()),
+        )
+    }
+
+    /// Builds a [`Cache`] implementation according to parameters set on the builder that weighs
+    /// each value by its size.
+    ///
+    /// The capacity is then the maximum total size of values in bytes rather than the maximum
+    /// number of entries. Suitable for values of varying size, such as contract bytecode.
+    pub fn build_weighted<K, V>(self) -> impl Cache<K, V>
+    where
+        K: Hash + Eq + Send + Sync + 'static,
+        V: AsRef<[u8]> + Clone + Send + Sync + 'static,
+    {
+        ConcurrentCache::new(
+            moka::sync::CacheBuilder::new(self.capacity.expect("Parameters are filled-in") as u64)
+                .eviction_policy(self.policy.expect("Parameters are filled-in").into())
+                .weigher(|_key, value: &V| u32::try_from(value.as_ref().len()).unwrap_or(u32::MAX)),
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::evm_state::{Account, Bytecode, B256};
+    use crate::Error;
     use primitive_types::U256;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
 
     #[test]
     fn test_builder_creates_cache_with_desired_capacity_that_evicts_lru() {
@@ -106,9 +167,9 @@ mod tests {
             .with_eviction_policy(EvictionPolicy::LeastRecentlyUsed)
             .build();
         let first_address = [0u8; 20];
-        let first_account = Account::new(0, U256::zero(), U256::zero(), U256::zero());
+        let first_account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
         let second_address = [1u8; 20];
-        let second_account = Account::new(1, U256::zero(), U256::zero(), U256::zero());
+        let second_account = Account::new(1, U256::zero(), B256::ZERO, B256::ZERO);
 
         cache.write(first_address, first_account);
         cache.write(second_address, second_account);
@@ -135,9 +196,9 @@ mod tests {
             .with_capacity(1)
             .build();
         let first_address = [0u8; 20];
-        let first_account = Account::new(0, U256::zero(), U256::zero(), U256::zero());
+        let first_account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
         let second_address = [1u8; 20];
-        let second_account = Account::new(1, U256::zero(), U256::zero(), U256::zero());
+        let second_account = Account::new(1, U256::zero(), B256::ZERO, B256::ZERO);
 
         cache.write(first_address, first_account.clone());
         cache.write(first_address, first_account);
@@ -157,4 +218,246 @@ mod tests {
             "Cache does not contain most frequently used entry"
         );
     }
+
+    #[test]
+    fn test_builder_creates_weighted_cache_that_rejects_values_larger_than_capacity() {
+        let cache = CacheBuilder::new()
+            .with_capacity(4)
+            .with_eviction_policy(EvictionPolicy::LeastRecentlyUsed)
+            .build_weighted();
+        let small_code = Bytecode::from(vec![0u8; 2]);
+        let large_code = Bytecode::from(vec![0u8; 8]);
+
+        cache.write(U256::zero(), small_code);
+        cache.write(U256::one(), large_code);
+
+        for _ in 0..100 {
+            cache.read(&U256::zero());
+            cache.read(&U256::one());
+        }
+
+        assert!(
+            cache.read(&U256::zero()).is_some(),
+            "Cache does not contain value that fits its capacity"
+        );
+        assert!(
+            cache.read(&U256::one()).is_none(),
+            "Cache contains value larger than its capacity"
+        );
+    }
+
+    #[test]
+    fn test_builder_creates_cache_that_records_statistics() {
+        let cache = CacheBuilder::new()
+            .with_capacity(1)
+            .with_eviction_policy(EvictionPolicy::LeastRecentlyUsed)
+            .build();
+        let first_address = [0u8; 20];
+        let first_account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
+        let second_address = [1u8; 20];
+        let second_account = Account::new(1, U256::zero(), B256::ZERO, B256::ZERO);
+
+        cache.write(first_address, first_account);
+        cache.read(&first_address);
+        cache.read_or_write_with(second_address, || Some(second_account));
+
+        let statistics = cache.statistics();
+
+        assert_eq!(1, statistics.hits);
+        assert_eq!(1, statistics.misses);
+        assert_eq!(2, statistics.inserts);
+        assert_eq!(1, statistics.evictions);
+        assert_eq!(1, statistics.entry_count);
+        assert_eq!(1, statistics.loads);
+    }
+
+    #[test]
+    fn test_builder_creates_lru_cache_that_evicts_exactly_least_recently_used() {
+        let cache = CacheBuilder::new().with_capacity(2).build_lru();
+        let first_address = [0u8; 20];
+        let first_account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
+        let second_address = [1u8; 20];
+        let second_account = Account::new(1, U256::zero(), B256::ZERO, B256::ZERO);
+        let third_address = [2u8; 20];
+        let third_account = Account::new(2, U256::zero(), B256::ZERO, B256::ZERO);
+
+        cache.write(first_address, first_account);
+        cache.write(second_address, second_account);
+        cache.read(&first_address);
+        cache.write(third_address, third_account);
+
+        assert!(
+            cache.contains(&first_address),
+            "Cache does not contain recently used entry"
+        );
+        assert!(
+            !cache.contains(&second_address),
+            "Cache contains evicted entry"
+        );
+        assert!(
+            cache.contains(&third_address),
+            "Cache does not contain most recently written entry"
+        );
+        assert_eq!(1, cache.statistics().evictions);
+    }
+
+    #[test]
+    fn test_builder_creates_lru_cache_that_reports_evicted_entries() {
+        let cache = CacheBuilder::new().with_capacity(2).build_lru();
+
+        let first_evicted = cache.write_evicting(1, "phylax");
+        let replaced_evicted = cache.write_evicting(1, "centurion");
+        cache.write(2, "optio");
+        let third_evicted = cache.write_evicting(3, "tesserarius");
+
+        assert_eq!(Vec::<(i32, &str)>::new(), first_evicted);
+        assert_eq!(Vec::<(i32, &str)>::new(), replaced_evicted);
+        assert_eq!(vec![(1, "centurion")], third_evicted);
+    }
+
+    #[test]
+    fn test_builder_creates_sharded_lru_cache_within_desired_capacity() {
+        let cache = CacheBuilder::new().with_capacity(4).build_sharded_lru(2);
+
+        for i in 0..100u8 {
+            cache.write(
+                [i; 20],
+                Account::new(i as u64, U256::zero(), B256::ZERO, B256::ZERO),
+            );
+        }
+
+        assert!(
+            cache.statistics().entry_count <= 4,
+            "Cache holds more entries than its capacity"
+        );
+    }
+
+    #[test]
+    fn test_builder_creates_sharded_lru_cache_that_reads_and_writes_in_batch() {
+        let cache = CacheBuilder::new().with_capacity(16).build_sharded_lru(4);
+        let entries: Vec<_> = (0..8u8)
+            .map(|i| {
+                (
+                    [i; 20],
+                    Account::new(i as u64, U256::zero(), B256::ZERO, B256::ZERO),
+                )
+            })
+            .collect();
+
+        cache.write_many(entries.clone());
+        let actual_accounts = cache.read_many(&[[3u8; 20], [100u8; 20], [0u8; 20]]);
+
+        assert_eq!(
+            vec![Some(entries[3].1.clone()), None, Some(entries[0].1.clone())],
+            actual_accounts
+        );
+        let statistics = cache.statistics();
+        assert_eq!(2, statistics.hits);
+        assert_eq!(1, statistics.misses);
+        assert_eq!(8, statistics.inserts);
+    }
+
+    #[test]
+    fn test_builder_creates_cache_that_records_statistics_of_batches() {
+        let cache = CacheBuilder::new()
+            .with_capacity(10)
+            .with_eviction_policy(EvictionPolicy::LeastRecentlyUsed)
+            .build();
+        let account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
+
+        cache.write_many(vec![([0u8; 20], account.clone()), ([1u8; 20], account)]);
+        cache.read_many(&[[0u8; 20], [1u8; 20], [2u8; 20]]);
+
+        let statistics = cache.statistics();
+
+        assert_eq!(2, statistics.hits);
+        assert_eq!(1, statistics.misses);
+        assert_eq!(2, statistics.inserts);
+    }
+
+    #[test]
+    fn test_builder_creates_cache_that_invalidates_entries_matching_predicate() {
+        let cache = CacheBuilder::new()
+            .with_capacity(10)
+            .with_eviction_policy(EvictionPolicy::LeastRecentlyUsed)
+            .build();
+        let account = Account::new(0, U256::zero(), B256::ZERO, B256::ZERO);
+        cache.write_many(vec![([0u8; 20], account.clone()), ([1u8; 20], account)]);
+
+        cache.invalidate_if(Box::new(|address, _account| address[0] == 0));
+
+        assert!(
+            !cache.contains(&[0u8; 20]),
+            "Cache contains invalidated entry"
+        );
+        assert!(
+            cache.contains(&[1u8; 20]),
+            "Cache does not contain entry not matching predicate"
+        );
+    }
+
+    #[test]
+    fn test_built_caches_are_usable_as_trait_objects() {
+        let caches: Vec<Box<dyn Cache<[u8; 20], u64>>> = vec![
+            Box::new(
+                CacheBuilder::new()
+                    .with_capacity(10)
+                    .with_eviction_policy(EvictionPolicy::LeastRecentlyUsed)
+                    .build(),
+            ),
+            Box::new(CacheBuilder::new().with_capacity(10).build_lru()),
+        ];
+
+        for cache in caches {
+            cache.write([0u8; 20], 1);
+            cache.invalidate_if(Box::new(|_address, nonce| *nonce == 1));
+
+            assert!(
+                !cache.contains(&[0u8; 20]),
+                "Cache contains invalidated entry"
+            );
+        }
+    }
+
+    #[test]
+    fn test_builder_creates_lru_cache_accessible_while_loading_missing_value() {
+        let cache = CacheBuilder::new().with_capacity(10).build_lru();
+
+        let value = cache.read_or_write_with(0u8, || {
+            cache.write(1u8, 1u64);
+            cache.read(&1u8)
+        });
+
+        assert_eq!(Some(1), value);
+        assert!(cache.contains(&0u8), "Loaded value not cached");
+    }
+
+    #[test]
+    fn test_builder_creates_lru_cache_that_loads_concurrent_misses_once() {
+        let cache = Arc::new(CacheBuilder::new().with_capacity(10).build_lru());
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let loads = Arc::clone(&loads);
+                thread::spawn(move || {
+                    cache.try_read_or_write_with(0u8, || {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(50));
+                        Err::<Option<u64>, _>(Error::backend("Database is unavailable"))
+                    })
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert!(
+                matches!(handle.join().unwrap(), Err(Error::Backend(_))),
+                "Failure not reported"
+            );
+        }
+        assert_eq!(1, loads.load(Ordering::SeqCst));
+        assert!(!cache.contains(&0u8), "Failed load cached");
+    }
 }
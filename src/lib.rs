@@ -17,7 +17,7 @@
 //!
 //! ```
 //! use revm::InMemoryDB;
-//! use evm_state_cache::{CacheBuilder, CachedEvmStateRepository, EvictionPolicy, EvmStateRepository, RevmStateRepository};
+//! use evm_state_cache::{Address, CacheBuilder, CachedEvmStateRepository, EvictionPolicy, EvmStateRepository, RevmStateRepository};
 //!
 //! // Create cache with provided options
 //! let cache = CacheBuilder::new()
@@ -35,7 +35,7 @@
 //! let repository = CachedEvmStateRepository::new(repository, cache);
 //!
 //! // Create an Ethereum address
-//! let address = [0u8; 20];
+//! let address: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse().unwrap();
 //!
 //! // Load account by given address
 //! let account = repository.get(&address);
@@ -44,9 +44,11 @@
 //! ```
 
 mod cache;
+mod error;
 mod evm_state;
 mod factory;
 
 pub use cache::*;
+pub use error::{Error, Result};
 pub use evm_state::*;
 pub use factory::{CacheBuilder, EvictionPolicy};